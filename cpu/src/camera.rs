@@ -0,0 +1,169 @@
+use serde::Deserialize;
+
+use crate::math::*;
+use crate::sampler::Sampler;
+use crate::sampling;
+use crate::ray::*;
+
+/// Camera as written in the scene file, a pinhole unless it has an aperture
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Camera {
+    pub position: Vec3,
+    /// Point in the middle of the image
+    pub target: Vec3,
+    /// Roughly up in the image, only has to be perpendicular enough to the view direction
+    pub up: Vec3,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+    /// Diameter of the lens, 0 keeps everything in focus
+    pub aperture: f32,
+    /// Distance to the plane that is in focus, the distance to `target` if not given
+    pub focus_distance: Option<f32>,
+    /// Number of straight aperture blades, 0 gives a round aperture
+    pub blades: u32,
+    /// Rotation of a bladed aperture in degrees
+    pub blade_rotation: f32,
+    /// Deprecated, the distance to a viewport one unit tall. Replaces `fov` when given.
+    pub viewport_distance: Option<f32>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::zero(),
+            target: Vec3::from(0.0, 0.0, 1.0),
+            up: Vec3::from(0.0, 1.0, 0.0),
+            // A viewport as tall as it is far away
+            fov: 2.0 * 0.5f32.atan().to_degrees(),
+            aperture: 0.0,
+            focus_distance: None,
+            blades: 0,
+            blade_rotation: 0.0,
+            viewport_distance: None,
+        }
+    }
+}
+
+impl Camera {
+    /// Check that the camera has a direction and a usable field of view
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(distance) = self.viewport_distance.filter(|&d| d.is_nan() || d <= 0.0) {
+            return Err(format!(
+                "viewport distance must be positive, got {}",
+                distance
+            ));
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(format!(
+                "fov must be between 0 and 180 degrees, got {}",
+                self.fov
+            ));
+        }
+        if self.aperture.is_nan() || self.aperture < 0.0 {
+            return Err(format!("aperture can't be negative, got {}", self.aperture));
+        }
+        if let Some(focus_distance) = self.focus_distance.filter(|&d| d.is_nan() || d <= 0.0) {
+            return Err(format!(
+                "focus distance must be positive, got {}",
+                focus_distance
+            ));
+        }
+        if self.blades == 1 || self.blades == 2 {
+            return Err(format!(
+                "an aperture needs 0 or at least 3 blades, got {}",
+                self.blades
+            ));
+        }
+        if self.matrix().is_none() {
+            return Err(
+                "camera target must differ from its position and not be straight along up"
+                    .to_string(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Primary rays for an image of `width` by `height` pixels, the aspect ratio follows the
+    /// resolution. Panics if the camera doesn't pass `validate`.
+    pub fn view(&self, width: u32, height: u32) -> View {
+        let half_height = (self.fov.to_radians() * 0.5).tan();
+
+        View {
+            to_world: self.matrix().expect("camera must be validated"),
+            half_width: half_height * width as f32 / height as f32,
+            half_height,
+            width: width as f32,
+            height: height as f32,
+            lens_radius: self.aperture * 0.5,
+            focus_distance: self
+                .focus_distance
+                .unwrap_or_else(|| (self.target - self.position).length()),
+            blades: self.blades,
+            blade_rotation: self.blade_rotation.to_radians(),
+        }
+    }
+
+    /// Camera to world
+    fn matrix(&self) -> Option<Mat4> {
+        Mat4::look_at(self.position, self.target, self.up)
+    }
+}
+
+/// Camera set up for one resolution
+pub struct View {
+    to_world: Mat4,
+    /// Half the viewport size at distance 1
+    half_width: f32,
+    half_height: f32,
+    width: f32,
+    height: f32,
+    lens_radius: f32,
+    focus_distance: f32,
+    blades: u32,
+    blade_rotation: f32,
+}
+
+impl View {
+    /// Ray through a point on the image in pixels, from the top left corner. With an aperture
+    /// the ray starts at a random point on the lens and passes through the focus plane where a
+    /// pinhole ray would.
+    pub fn ray(&self, x: f32, y: f32, sampler: &mut dyn Sampler) -> Ray {
+        let direction = Vec3::from(
+            (2.0 * x / self.width - 1.0) * self.half_width,
+            (1.0 - 2.0 * y / self.height) * self.half_height,
+            1.0,
+        );
+
+        let (origin, direction) = if self.lens_radius > 0.0 {
+            let lens = self.sample_aperture(sampler) * self.lens_radius;
+            (lens, direction * self.focus_distance - lens)
+        } else {
+            (Vec3::zero(), direction)
+        };
+
+        Ray {
+            origin: self.to_world.transform_point(&origin),
+            direction: self.to_world.transform_vector(&direction).normalized(),
+        }
+    }
+
+    /// Uniform point on the unit aperture in the camera's xy plane
+    fn sample_aperture(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let u = sampler.next_2d();
+
+        if self.blades == 0 {
+            return sampling::uniform_disk(u).0;
+        }
+
+        // Pick one of the equal triangles between the centre and two neighbouring corners
+        let blade = ((sampler.next_1d() * self.blades as f32) as u32).min(self.blades - 1);
+        let step = std::f32::consts::TAU / self.blades as f32;
+        let angle = self.blade_rotation + blade as f32 * step;
+        let a = Vec3::from(angle.cos(), angle.sin(), 0.0);
+        let b = Vec3::from((angle + step).cos(), (angle + step).sin(), 0.0);
+
+        sampling::uniform_triangle(u, Vec3::zero(), a, b).0
+    }
+}
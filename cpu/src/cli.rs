@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::output::{Format, Overwrite};
+use crate::sampler::SamplerKind;
+use crate::scene::*;
+
+#[derive(Parser)]
+#[command(about = "CPU path tracer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Render a scene to an image
+    Render {
+        #[command(flatten)]
+        scene: SceneArgs,
+
+        /// Where to write the image, missing directories are created
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Image format, picked from the output extension if not given
+        #[arg(short, long, value_enum)]
+        format: Option<Format>,
+
+        /// What to do if the output file already exists
+        #[arg(long, value_enum, default_value_t)]
+        overwrite: Overwrite,
+    },
+    /// Render a scene several times and print how long it took
+    Bench {
+        #[command(flatten)]
+        scene: SceneArgs,
+
+        /// Number of timed renders
+        #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+    /// Time rays against a triangle mesh with and without its BVH
+    BenchMesh {
+        /// Approximate number of triangles in the test mesh
+        #[arg(short, long, default_value_t = 100_000, value_parser = clap::value_parser!(u32).range(2..))]
+        triangles: u32,
+
+        /// Number of rays to cast
+        #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+        rays: u32,
+    },
+    /// Print the scene and render settings without rendering
+    Info {
+        #[command(flatten)]
+        scene: SceneArgs,
+    },
+}
+
+/// Scene file and overrides for its render settings
+#[derive(Args)]
+pub struct SceneArgs {
+    /// Scene description file, uses the built-in scene if not given
+    pub scene: Option<String>,
+
+    /// Image size as WIDTHxHEIGHT
+    #[arg(short, long, value_parser = parse_resolution)]
+    pub resolution: Option<(u32, u32)>,
+
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+
+    /// Maximum number of bounces per path
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: Option<u32>,
+
+    /// Number of render threads, 0 uses one per core
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Seed for the random number generator
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// How the random numbers of a pixel's samples are spread out
+    #[arg(long, value_enum)]
+    pub sampler: Option<SamplerKind>,
+
+    /// Test every object for every ray instead of using the BVH
+    #[arg(long)]
+    pub no_bvh: bool,
+}
+
+impl SceneArgs {
+    /// Apply the overrides on top of the scene file's settings
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        if let Some((width, height)) = self.resolution {
+            settings.width = width;
+            settings.height = height;
+        }
+        if let Some(samples) = self.samples {
+            settings.samples = samples;
+        }
+        if let Some(depth) = self.depth {
+            settings.max_depth = depth;
+        }
+        if let Some(threads) = self.threads {
+            settings.threads = threads;
+        }
+        if self.seed.is_some() {
+            settings.seed = self.seed;
+        }
+        if let Some(sampler) = self.sampler {
+            settings.sampler = sampler;
+        }
+        if self.no_bvh {
+            settings.bvh = false;
+        }
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got `{}`", s))?;
+    let width = width
+        .parse::<u32>()
+        .map_err(|e| format!("invalid width `{}`: {}", width, e))?;
+    let height = height
+        .parse::<u32>()
+        .map_err(|e| format!("invalid height `{}`: {}", height, e))?;
+
+    if width == 0 || height == 0 {
+        return Err("width and height must be at least 1".to_string());
+    }
+
+    Ok((width, height))
+}
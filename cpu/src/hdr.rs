@@ -0,0 +1,273 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::math::Vec3;
+
+#[derive(Debug)]
+pub enum HdrError {
+    Io { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, message: String },
+}
+
+impl Display for HdrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for HdrError {}
+
+/// Image with linear colours, stored as rows from the top
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+/// Read a Radiance RGBE .hdr file, with flat or run length encoded scanlines
+pub fn load(path: &Path) -> Result<HdrImage, HdrError> {
+    let data = fs::read(path).map_err(|error| HdrError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    decode(&data).map_err(|message| HdrError::Parse {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+fn decode(data: &[u8]) -> Result<HdrImage, String> {
+    let mut reader = Reader { data, position: 0 };
+
+    if !reader.line()?.starts_with("#?") {
+        return Err("not a Radiance HDR file".to_string());
+    }
+
+    // The header ends with an empty line
+    loop {
+        let line = reader.line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported format `{}`", format));
+            }
+        }
+    }
+
+    let resolution = reader.line()?;
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (width.parse().ok(), height.parse().ok()),
+        _ => (None, None),
+    };
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err(format!(
+            "unsupported resolution `{}`, expected `-Y height +X width`",
+            resolution
+        ));
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+
+    for _ in 0..height {
+        reader.scanline(&mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| from_rgbe(rgbe)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, count: usize) -> Result<&[u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or("file ends in the middle of the image")?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn line(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.position..];
+        let end = rest
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or("file ends in the header")?;
+        self.position += end + 1;
+
+        Ok(String::from_utf8_lossy(&rest[..end]).trim().to_string())
+    }
+
+    fn scanline(&mut self, scanline: &mut [[u8; 4]]) -> Result<(), String> {
+        let width = scanline.len();
+        let start = self.data.get(self.position..self.position + 4);
+
+        // Run length encoded scanlines start with 2, 2 and the width, and store each channel
+        // separately
+        let encoded = (8..0x8000).contains(&width)
+            && start.is_some_and(|start| {
+                start[0] == 2
+                    && start[1] == 2
+                    && (usize::from(start[2]) << 8 | usize::from(start[3])) == width
+            });
+
+        if !encoded {
+            for pixel in scanline.iter_mut() {
+                pixel.copy_from_slice(self.bytes(4)?);
+            }
+            return Ok(());
+        }
+
+        self.position += 4;
+
+        for channel in 0..4 {
+            let mut x = 0;
+
+            while x < width {
+                let count = self.byte()?;
+                // Counts above 128 repeat the next byte, the others are followed by that many
+                // bytes
+                let length = if count > 128 { count - 128 } else { count } as usize;
+
+                if length == 0 || x + length > width {
+                    return Err("scanline run is longer than the image row".to_string());
+                }
+
+                if count > 128 {
+                    let value = self.byte()?;
+                    for pixel in &mut scanline[x..x + length] {
+                        pixel[channel] = value;
+                    }
+                } else {
+                    let values = self.bytes(length)?;
+                    for (pixel, &value) in scanline[x..x + length].iter_mut().zip(values) {
+                        pixel[channel] = value;
+                    }
+                }
+
+                x += length;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Inverse of the shared exponent encoding, taking each mantissa from the middle of its step
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::zero();
+    }
+
+    // 2^-136 is past what an f32 holds, the small exponents need an f64 to scale down
+    let scale = 2f64.powi(e as i32 - 128 - 8);
+    let channel = |mantissa: u8| ((f64::from(mantissa) + 0.5) * scale) as f32;
+    Vec3::from(channel(r), channel(g), channel(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Format;
+
+    fn round_trip(image: &[Vec3], width: u32) -> HdrImage {
+        let height = image.len() as u32 / width;
+        let data = Format::Hdr.encode(width, height, image).unwrap();
+        let decoded = decode(&data).unwrap();
+
+        assert_eq!(
+            (decoded.width, decoded.height),
+            (width as usize, height as usize)
+        );
+        decoded
+    }
+
+    #[test]
+    fn written_images_read_back_within_rgbe_precision() {
+        let image = [
+            Vec3::from(0.5, 0.25, 0.125),
+            Vec3::from(1.0, 0.0, 0.0),
+            Vec3::from(10.0, 3.0, 0.01),
+            Vec3::from(12345.0, 1.0, 678.0),
+            Vec3::from(1e-3, 2e-3, 4e-3),
+            Vec3::from(3e30, 2e30, 1e30),
+            Vec3::from(1e-36, 5e-37, 0.0),
+            // Denormal, but still above the smallest exponent
+            Vec3::from(2e-38, 0.0, 1e-38),
+        ];
+        let decoded = round_trip(&image, 4);
+
+        // Mantissas have 8 bits and the largest is at least 128, read back from the middle of
+        // their step
+        for (pixel, original) in decoded.pixels.iter().zip(image) {
+            let max = original.x.max(original.y).max(original.z);
+            for (a, b) in [
+                (pixel.x, original.x),
+                (pixel.y, original.y),
+                (pixel.z, original.z),
+            ] {
+                assert!(
+                    (a - b).abs() <= max / 256.0,
+                    "{:?} != {:?}",
+                    pixel,
+                    original
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unrepresentable_pixels_are_clamped() {
+        let image = [
+            Vec3::zero(),
+            Vec3::from(-1.0, -2.0, -3.0),
+            Vec3::from(f32::NAN, 0.0, 0.0),
+            // Below 2^-128, darker than the smallest exponent
+            Vec3::from(1e-40, 1e-45, 0.0),
+            Vec3::from(-1.0, 2.0, -3.0),
+            Vec3::from(f32::INFINITY, 1.0, 0.0),
+            Vec3::from(f32::MAX, 0.0, 0.0),
+            Vec3::from(1e38, 0.0, 0.0),
+        ];
+        let decoded = round_trip(&image, 2);
+
+        for pixel in &decoded.pixels[..4] {
+            assert_eq!(*pixel, Vec3::zero());
+        }
+
+        // Negative channels are dropped, the others keep their value
+        let pixel = decoded.pixels[4];
+        assert!(
+            pixel.x <= 2.0 / 256.0 && pixel.z <= 2.0 / 256.0,
+            "{:?}",
+            pixel
+        );
+        assert!((pixel.y - 2.0).abs() <= 2.0 / 256.0, "{:?}", pixel);
+
+        // Too bright pixels saturate at the largest value RGBE stores
+        let largest = 255.5 * 2f32.powi(127 - 8);
+        assert_eq!(decoded.pixels[5].x, largest);
+        assert_eq!(decoded.pixels[6].x, largest);
+        assert!((decoded.pixels[7].x - 1e38).abs() <= 1e38 / 256.0);
+    }
+}
@@ -1,16 +1,136 @@
 use crate::math::*;
-use crate::material::*;
-use crate::object::*;
-use crate::mesh::*;
-
-pub struct PointLight;
-
-impl PointLight {
-    pub fn new(position: Vec3, radius: f32, range: f32, color: Color) -> Sphere {
-        Object::sphere(
-            position, 
-            radius, 
-            Box::new(PointLightMaterial { color })
-        )
+
+/// Light without a body, it can't be hit by rays and is only found through shadow rays
+pub trait Light: Send + Sync {
+    /// Light arriving at `point`, `None` if none of it does
+    fn sample(&self, point: Vec3) -> Option<LightSample>;
+}
+
+pub struct LightSample {
+    /// Unit direction from the lit point towards the light
+    pub direction: Vec3,
+    /// Distance to the light, infinite for directional lights
+    pub distance: f32,
+    /// Irradiance on a surface facing the light
+    pub value: Vec3,
+}
+
+/// Shines equally in every direction from a single point
+pub struct PointLight {
+    pub position: Vec3,
+    /// Colour times intensity, in watts per steradian
+    pub intensity: Vec3,
+    /// Distance where the light has faded out completely, `None` lets it reach everywhere
+    pub range: Option<f32>,
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let (direction, distance) = towards(point, self.position)?;
+
+        Some(LightSample {
+            direction,
+            distance,
+            value: self.intensity * attenuation(distance, self.range),
+        })
+    }
+}
+
+/// Point light that only shines inside a cone
+pub struct SpotLight {
+    pub position: Vec3,
+    /// Unit direction the cone points in
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    pub range: Option<f32>,
+    /// Cosine of the angle between the axis and the edge of the cone
+    pub cos_outer: f32,
+    /// Cosine of the angle where the light starts fading towards the edge
+    pub cos_inner: f32,
+}
+
+impl SpotLight {
+    /// Spot light with a cone of `angle` degrees from the axis to the edge, fading out over the
+    /// outer `falloff` degrees of it
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        range: Option<f32>,
+        angle: f32,
+        falloff: f32,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.normalized(),
+            intensity,
+            range,
+            cos_outer: angle.to_radians().cos(),
+            cos_inner: (angle - falloff).to_radians().cos(),
+        }
+    }
+
+    fn cone(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
     }
-}
\ No newline at end of file
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let (direction, distance) = towards(point, self.position)?;
+        let cone = self.cone(-direction.dot(&self.direction));
+
+        if cone <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            value: self.intensity * (cone * attenuation(distance, self.range)),
+        })
+    }
+}
+
+/// Light from infinitely far away that reaches everything from one direction, like the sun
+pub struct DirectionalLight {
+    /// Unit direction the light travels in
+    pub direction: Vec3,
+    /// Colour times intensity, in watts per square metre
+    pub irradiance: Vec3,
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: f32::INFINITY,
+            value: self.irradiance,
+        })
+    }
+}
+
+/// Unit direction and distance from `point` to `position`, `None` if they are the same point
+fn towards(point: Vec3, position: Vec3) -> Option<(Vec3, f32)> {
+    let offset = position - point;
+    let distance = offset.length();
+
+    (distance > 0.0).then(|| (offset / distance, distance))
+}
+
+/// Inverse square falloff, windowed to reach 0 at `range` the way glTF does it
+fn attenuation(distance: f32, range: Option<f32>) -> f32 {
+    let window = range.map_or(1.0, |range| {
+        (1.0 - (distance / range).powi(4)).clamp(0.0, 1.0).powi(2)
+    });
+
+    window / (distance * distance)
+}
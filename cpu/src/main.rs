@@ -1,152 +1,559 @@
-use std::{fs, path::Path};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{process, thread};
+
+use clap::Parser;
 
 use crate::math::*;
+use crate::mesh::*;
+use crate::random::Rng;
 use crate::ray::*;
+use crate::sampler::Sampler;
 // use crate::primitives::*;
-use crate::material::*;
-use crate::object::*;
+use crate::cli::*;
+use crate::scene::*;
 
+mod bvh;
+mod camera;
+mod cli;
+mod hdr;
 mod math;
 mod ray;
 // mod primitives;
 mod material;
 mod mesh;
+mod obj;
 mod object;
-// mod lights;
+mod output;
+mod lights;
+mod random;
+mod sampler;
+mod sampling;
+mod scene;
+mod sky;
 
-const WIDTH: u32 = 512;
-const HEIGHT: u32 = WIDTH;
-const VIEWPORT_DISTANCE: f32 = 1.0;
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.toml");
 
-const SKY_COLOR: Vec3 = /* Vec3 { x: 0.0, y: 0.0, z: 0.0 }; */
-    Vec3 {
-        x: 0.5,
-        y: 0.7,
-        z: 1.0,
-    };
+#[derive(Clone, Copy, Debug)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
 
-const CAMERA_POSITION: Vec3 = Vec3 {
-    x: 0.0,
-    y: 0.0,
-    z: 0.0,
-};
+fn main() {
+    let cli = Cli::parse();
 
-const MAX_DEPTH: u32 = 10;
-const NUM_SAMPLES: u32 = 20;
+    match cli.command {
+        Command::Render {
+            scene,
+            output,
+            format,
+            overwrite,
+        } => {
+            let scene = load_scene(&scene);
 
-fn main() {
-    let mut current = 0;
-    let mut current_path = format!("cpu/images/image{}.ppm", current);
-    while Path::new(&current_path).exists() {
-        current += 1;
-        current_path = format!("cpu/images/image{}.ppm", current);
-    }
-    println!("image path: {}", current_path);
-
-    let mut image = format!("P3\n{} {}\n255\n", WIDTH, HEIGHT);
-
-    let mut objects = Vec::new();
-    objects.push(Object::sphere(
-        Vec3::from(0.0, 0.0, 4.0),
-        0.7,
-        Diffuse::boxed(Vec3::from(0.5, 0.5, 0.5).into()),
-        objects.len(),
-    ));
-    objects.push(Object::point_light(
-        Vec3::from(-2.0, 0.5, 4.0),
-        0.7,
-        100.0,
-        (Vec3::one() * 10.0).into(),
-        objects.len(),
-    ));
-    objects.push(Object::point_light(
-        Vec3::from(0.0, 1.7, 4.0),
-        0.7,
-        100.0,
-        (Vec3::one() * 10.0).into(),
-        objects.len(),
-    ));
-    objects.push(Object::sphere(
-        Vec3::from(100.7, 0.0, 4.0),
-        100.0,
-        Diffuse::boxed(Vec3::from(0.5, 1.0, 0.3).into()),
-        objects.len(),
-    ));
-
-    image += cpu_compute(&objects)
-    .iter()
-    .map(|x| Color::from(*x).to_string())
-    .collect::<Vec<String>>()
-    .chunks(WIDTH as usize)
-    .map(|x| x.join(" "))
-    .collect::<Vec<String>>()
-    .as_slice()
-    .join("\n")
-    .as_str();
-
-    println!("image path: {}", &current_path);
-    fs::write(&current_path, image).unwrap();
+            let format = output::check(&output, format, overwrite).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            });
+
+            let image = cpu_compute(&scene);
+
+            match format
+                .encode(scene.settings.width, scene.settings.height, &image)
+                .and_then(|data| output::write(&output, overwrite, &data))
+            {
+                Ok(path) => println!("image path: {}", path.display()),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
+            }
+        }
+        Command::Bench { scene, iterations } => {
+            let scene = load_scene(&scene);
+            let mut times = Vec::new();
+
+            for i in 0..iterations {
+                let start = Instant::now();
+                cpu_compute(&scene);
+                times.push(start.elapsed());
+                println!("iteration {}: {:?}", i + 1, times[i as usize]);
+            }
+
+            let total: Duration = times.iter().sum();
+            println!(
+                "min {:?}, mean {:?}, max {:?}",
+                times.iter().min().unwrap(),
+                total / iterations,
+                times.iter().max().unwrap()
+            );
+        }
+        Command::BenchMesh { triangles, rays } => bench_mesh(triangles, rays),
+        Command::Info { scene } => {
+            let scene = load_scene(&scene);
+            let settings = &scene.settings;
+
+            println!("objects: {}", scene.objects.len());
+            println!("lights: {}", scene.lights.len());
+            println!("camera: {:?}", scene.camera);
+            println!("sky: {:?}", scene.sky);
+            println!("resolution: {}x{}", settings.width, settings.height);
+            println!("samples: {}", settings.samples);
+            println!("max depth: {}", settings.max_depth);
+            println!("threads: {}", settings.threads());
+            println!("tile size: {}", settings.tile_size);
+            println!("sampler: {:?}", settings.sampler);
+            match settings.seed {
+                Some(seed) => println!("seed: {}", seed),
+                None => println!("seed: random"),
+            }
+        }
+    }
+}
+
+/// Compare casting random rays at a UV sphere mesh with its BVH and by testing every triangle
+fn bench_mesh(triangles: u32, rays: u32) {
+    let segments = ((triangles as f32 / 2.0).sqrt().ceil() as usize).max(2);
+
+    let start = Instant::now();
+    let mesh = Mesh::uv_sphere(Vec3::zero(), 1.0, segments, segments);
+    println!(
+        "built mesh with {} triangles in {:?}",
+        mesh.triangle_count(),
+        start.elapsed()
+    );
+
+    let mut rng = Rng::new(0, 0);
+    let rays = (0..rays)
+        .map(|_| {
+            let origin = Vec3::random_unit_vector(&mut rng) * 3.0;
+            let target = Vec3::random_unit_vector(&mut rng) * rng.next_f32() * 1.2;
+            Ray {
+                origin,
+                direction: (target - origin).normalized(),
+            }
+        })
+        .collect::<Vec<Ray>>();
+
+    let start = Instant::now();
+    let bvh_hits = rays
+        .iter()
+        .map(|ray| mesh.hit(ray, 0.01, f32::MAX, 0).map(|hit| hit.t))
+        .collect::<Vec<Option<f32>>>();
+    let bvh_time = start.elapsed();
+
+    let start = Instant::now();
+    let linear_hits = rays
+        .iter()
+        .map(|ray| mesh.hit_linear(ray, 0.01, f32::MAX, 0).map(|hit| hit.t))
+        .collect::<Vec<Option<f32>>>();
+    let linear_time = start.elapsed();
+
+    println!("bvh: {:?} ({:?} per ray)", bvh_time, bvh_time / rays.len() as u32);
+    println!(
+        "linear: {:?} ({:?} per ray)",
+        linear_time,
+        linear_time / rays.len() as u32
+    );
+    println!(
+        "speedup: {:.1}x",
+        linear_time.as_secs_f64() / bvh_time.as_secs_f64()
+    );
+
+    if bvh_hits != linear_hits {
+        eprintln!("bvh and linear hits differ");
+        process::exit(1);
+    }
+}
+
+/// Load the scene given on the command line, or the built-in one, and apply the overrides.
+/// Exits with an error message if the scene or the settings are invalid.
+fn load_scene(args: &SceneArgs) -> Scene {
+    let mut scene = match &args.scene {
+        Some(path) => scene::load(path),
+        None => scene::parse(DEFAULT_SCENE, "default.toml"),
+    }
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+
+    args.apply(&mut scene.settings);
+
+    if let Err(error) = scene.settings.validate() {
+        eprintln!("invalid render settings: {}", error);
+        process::exit(1);
+    }
+
+    scene
 }
 
-fn cpu_compute(objects: &Vec<Object>) -> Vec<Vec3> {
-    let mut image = Vec::new();
-
-    for x in 0..WIDTH {
-        for y in 0..HEIGHT {
-            let viewport_x = (x as f32 - WIDTH as f32 / 2.0) / WIDTH as f32;
-            let viewport_y = (y as f32 - HEIGHT as f32 / 2.0) / HEIGHT as f32;
-
-            let average_color = (0..NUM_SAMPLES).fold(Vec3::zero(), |acc, _| {
-                let ray = Ray {
-                    origin: CAMERA_POSITION,
-                    direction: Vec3 {
-                        x: viewport_x - (0.5 + rand::random::<f32>()) / WIDTH as f32,
-                        y: viewport_y - (0.5 + rand::random::<f32>()) / HEIGHT as f32,
-                        z: VIEWPORT_DISTANCE,
-                    }
-                    .normalized(),
-                };
-                acc + ray_caste(&ray, &objects, MAX_DEPTH)
-            }) / NUM_SAMPLES as f32;
-
-            image.push(average_color);
-            // println!("pixel: {} / {}", x * WIDTH + y, WIDTH * HEIGHT);
+/// Renders the image in tiles on `settings.threads` worker threads. The pixels are stored as
+/// rows from the top, `image[y * width + x]`.
+fn cpu_compute(scene: &Scene) -> Vec<Vec3> {
+    let settings = &scene.settings;
+    let tiles = tiles(settings);
+    // Without a seed every render is different, but the threads still have to agree on one
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let next_tile = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..settings.threads().min(tiles.len()) {
+            let sender = sender.clone();
+            let tiles = &tiles;
+            let next_tile = &next_tile;
+
+            s.spawn(move || {
+                while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                    sender.send((*tile, render_tile(tile, scene, seed))).unwrap();
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut image = vec![Vec3::zero(); (settings.width * settings.height) as usize];
+
+    for (tile, pixels) in receiver {
+        for (i, color) in pixels.into_iter().enumerate() {
+            let x = tile.x + i as u32 % tile.width;
+            let y = tile.y + i as u32 / tile.width;
+            image[(y * settings.width + x) as usize] = color;
         }
     }
 
     image
 }
 
-fn ray_caste(ray: &Ray, objects: &Vec<Object>, depth: u32) -> Vec3 {
+fn tiles(settings: &RenderSettings) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+
+    for y in (0..settings.height).step_by(settings.tile_size as usize) {
+        for x in (0..settings.width).step_by(settings.tile_size as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: settings.tile_size.min(settings.width - x),
+                height: settings.tile_size.min(settings.height - y),
+            });
+        }
+    }
+
+    tiles
+}
+
+fn render_tile(tile: &Tile, scene: &Scene, seed: u64) -> Vec<Vec3> {
+    let settings = &scene.settings;
+    let view = scene.camera.view(settings.width, settings.height);
+    let mut sampler = settings.sampler.create(seed, settings.samples);
+    let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+
+    for y in tile.y..tile.y + tile.height {
+        for x in tile.x..tile.x + tile.width {
+            let pixel = (y * settings.width + x) as u64;
+
+            let average_color = (0..settings.samples).fold(Vec3::zero(), |acc, sample| {
+                sampler.start(pixel, sample);
+                let [jitter_x, jitter_y] = sampler.next_2d();
+                let ray = view.ray(x as f32 + jitter_x, y as f32 + jitter_y, sampler.as_mut());
+                acc + ray_caste(&ray, scene, settings.max_depth, None, sampler.as_mut())
+            }) / settings.samples as f32;
+
+            pixels.push(average_color);
+        }
+    }
+
+    pixels
+}
+
+/// Light coming back along `ray`. `bsdf_pdf` is the density the previous bounce sampled the
+/// ray's direction with, `None` for camera rays and delta bounces that light sampling can't
+/// find.
+fn ray_caste(
+    ray: &Ray,
+    scene: &Scene,
+    depth: u32,
+    bsdf_pdf: Option<f32>,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
     if depth <= 0 {
         return Vec3::zero();
     }
 
-    let mut hit_record = None;
-    let mut closest_so_far = f32::MAX;
+    if let Some(hit_record) = scene.hit(ray, 0.01, f32::MAX) {
+        let material = &scene.objects[hit_record.object_id].material;
+        let mut color = material.emitted(&hit_record);
 
-    for object in objects {
-        if let Some(temp_hit_record) = object.hit(ray, 0.01, closest_so_far) {
-            closest_so_far = temp_hit_record.t;
-            hit_record = Some(temp_hit_record);
+        // The previous bounce also sampled this light directly, weigh the two against each other
+        let light_pdf = scene.emitter_pdf(ray.origin, &hit_record);
+        if let Some(bsdf_pdf) = bsdf_pdf.filter(|_| light_pdf > 0.0) {
+            let cos_theta = ray.direction.normalized().dot(&hit_record.geometric_normal).abs();
+            let distance = (hit_record.point - ray.origin).length();
+            color *= power_heuristic(bsdf_pdf, light_pdf * distance * distance / cos_theta);
         }
+
+        color += direct_light(ray, &hit_record, scene, sampler);
+
+        // Directions right along the surface can come with a density of 0
+        let Some(sample) = material
+            .sample(ray, &hit_record, sampler)
+            .filter(|sample| sample.pdf > 0.0)
+        else {
+            return color;
+        };
+
+        let scattered = hit_record.spawn_ray(sample.direction);
+        let bsdf_pdf = (!sample.delta).then_some(sample.pdf);
+
+        return color
+            + sample.value / sample.pdf
+                * ray_caste(&scattered, scene, depth - 1, bsdf_pdf, sampler);
     }
 
-    if let Some(hit_record) = hit_record {
-        let (scattered, attenuation) = objects[hit_record.object_id]
-            .material
-            .scatter(ray, &hit_record);
+    let radiance = scene.sky.radiance(ray.direction);
 
-        if let Some(scattered) = scattered {
-            return attenuation * ray_caste(&scattered, objects, depth - 1);
-        } else {
-            return attenuation;
-        }
+    // Like emitters, skies that are sampled directly split the light between both strategies
+    match bsdf_pdf {
+        Some(bsdf_pdf) => radiance * power_heuristic(bsdf_pdf, scene.sky.pdf(ray.direction)),
+        None => radiance,
+    }
+}
+
+/// Next event estimation, light that reaches the hit straight from every light and from a
+/// point sampled on one emitter
+fn direct_light(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let material = &scene.objects[hit_record.object_id].material;
+
+    let lights = scene
+        .lights
+        .iter()
+        .filter_map(|light| light.sample(hit_record.point))
+        .fold(Vec3::zero(), |acc, sample| {
+            let value = material.eval(ray, hit_record, sample.direction);
+
+            // Delta materials can't be lit this way
+            if value == Vec3::zero()
+                || !visible(scene, hit_record, sample.direction, sample.distance)
+            {
+                return acc;
+            }
+
+            acc + value * sample.value
+        });
+
+    lights
+        + emitter_light(ray, hit_record, scene, sampler)
+        + sky_light(ray, hit_record, scene, sampler)
+}
+
+fn emitter_light(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let Some((point, normal, emitter, pdf)) =
+        scene.sample_emitter(hit_record.point, sampler.next_2d(), sampler.next_1d())
+    else {
+        return Vec3::zero();
+    };
+
+    let to_emitter = point - hit_record.point;
+    let distance = to_emitter.length();
+    let direction = to_emitter / distance;
+    let cos_theta = direction.dot(&normal).abs();
+
+    let material = &scene.objects[hit_record.object_id].material;
+    let value = material.eval(ray, hit_record, direction);
+
+    // Delta materials, and emitters seen exactly edge on, can't be lit this way
+    if value == Vec3::zero()
+        || cos_theta <= 0.0
+        || !visible(scene, hit_record, direction, distance)
+    {
+        return Vec3::zero();
+    }
+
+    let shadow_ray = Ray {
+        origin: hit_record.point,
+        direction,
+    };
+    let emitter_hit_record = HitRecord::new(&shadow_ray, point, normal, distance, emitter);
+    let emitted = scene.objects[emitter].material.emitted(&emitter_hit_record);
+    let light_pdf = pdf * distance * distance / cos_theta;
+    let weight = power_heuristic(light_pdf, material.pdf(ray, hit_record, direction));
+
+    emitted * value * (weight / light_pdf)
+}
+
+fn sky_light(ray: &Ray, hit_record: &HitRecord, scene: &Scene, sampler: &mut dyn Sampler) -> Vec3 {
+    let Some((direction, pdf)) = scene.sky.sample(sampler.next_2d()) else {
+        return Vec3::zero();
+    };
+
+    let material = &scene.objects[hit_record.object_id].material;
+    let value = material.eval(ray, hit_record, direction);
+
+    if value == Vec3::zero() || !visible(scene, hit_record, direction, f32::INFINITY) {
+        return Vec3::zero();
+    }
+
+    let weight = power_heuristic(pdf, material.pdf(ray, hit_record, direction));
+
+    scene.sky.radiance(direction) * value * (weight / pdf)
+}
+
+/// Whether nothing blocks the way from the hit to `distance` along `direction`
+fn visible(scene: &Scene, hit_record: &HitRecord, direction: Vec3, distance: f32) -> bool {
+    let shadow_ray = hit_record.spawn_ray(direction);
+
+    scene.hit(&shadow_ray, 0.01, distance - 0.01).is_none()
+}
+
+/// Weight of a sample from the strategy with density `a` when `b` could also have made it
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    let (a, b) = (a * a, b * b);
+
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerKind;
+
+    /// A bit of everything, small enough to render in a test. Tiles of 5 don't divide the image
+    /// evenly.
+    const SCENE: &str = r#"
+        [camera]
+        position = [0.0, 1.0, -4.0]
+        target = [0.0, 0.5, 0.0]
+        fov = 50.0
+        aperture = 0.05
+
+        [sky]
+        type = "gradient"
+        bottom = [0.2, 0.2, 0.2]
+        top = [0.4, 0.6, 1.0]
+
+        [render]
+        width = 23
+        height = 17
+        samples = 3
+        max_depth = 4
+        tile_size = 5
+        seed = 7
+
+        [materials.white]
+        type = "diffuse"
+        color = [0.8, 0.8, 0.8]
+
+        [materials.gold]
+        type = "metal"
+        color = [1.0, 0.8, 0.4]
+        roughness = 0.3
+
+        [materials.glass]
+        type = "dielectric"
+        refractive_index = 1.5
+
+        [materials.plastic]
+        type = "pbr"
+        base_color = [0.2, 0.4, 0.9]
+        roughness = 0.4
+
+        [materials.lamp]
+        type = "emissive"
+        radiance = [4.0, 4.0, 4.0]
+
+        [[planes]]
+        point = [0.0, 0.0, 0.0]
+        normal = [0.0, 1.0, 0.0]
+        material = "white"
+
+        [[spheres]]
+        center = [-1.0, 0.5, 0.0]
+        radius = 0.5
+        material = "gold"
+
+        [[spheres]]
+        center = [0.2, 0.4, -0.8]
+        radius = 0.4
+        material = "glass"
+
+        [[quads]]
+        corner = [-0.5, 2.0, -0.5]
+        u = [1.0, 0.0, 0.0]
+        v = [0.0, 0.0, 1.0]
+        material = "lamp"
+
+        [[meshes]]
+        position = [1.0, 0.0, 0.3]
+        rotation = [0.0, 30.0, 0.0]
+        vertices = [[0.0, 0.0, 0.0], [0.8, 0.0, 0.0], [0.0, 0.0, 0.8], [0.2, 1.0, 0.2]]
+        indices = [0, 2, 1, 0, 1, 3, 1, 2, 3, 2, 0, 3]
+        material = "plastic"
+
+        [[point_lights]]
+        position = [1.5, 1.5, -1.5]
+        radius = 0.2
+        color = [5.0, 5.0, 5.0]
+
+        [[lights]]
+        type = "spot"
+        position = [-2.0, 2.0, -2.0]
+        direction = [1.0, -1.0, 1.0]
+        intensity = 3.0
+        angle = 30.0
+    "#;
+
+    fn render(sampler: SamplerKind, threads: usize, bvh: bool) -> Vec<Vec3> {
+        let mut scene = scene::parse(SCENE, "test.toml").unwrap();
+        scene.settings.sampler = sampler;
+        scene.settings.threads = threads;
+        scene.settings.bvh = bvh;
+        cpu_compute(&scene)
     }
 
-    if depth == 0 {
-        return SKY_COLOR;
+    const SAMPLERS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn threads_render_the_same_image() {
+        for sampler in SAMPLERS {
+            let image = render(sampler, 1, true);
+            assert!(image.iter().any(|color| *color != Vec3::zero()));
+            assert_eq!(image, render(sampler, 4, true), "{:?}", sampler);
+        }
     }
 
-    Vec3::zero() //SKY_COLOR
+    #[test]
+    fn bvh_renders_the_same_image() {
+        for sampler in SAMPLERS {
+            assert_eq!(
+                render(sampler, 2, true),
+                render(sampler, 2, false),
+                "{:?}",
+                sampler
+            );
+        }
+    }
 }
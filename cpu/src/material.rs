@@ -1,7 +1,10 @@
+use std::f32::consts::{PI, TAU};
 use std::fmt::Display;
 
 use crate::math::Vec3;
 use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::sampling;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Color {
@@ -49,8 +52,111 @@ impl std::ops::Mul<f32> for Color {
     }
 }
 
-pub trait Material {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Option<Ray>, Vec3);
+/// Direction picked by `Material::sample`
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    pub direction: Vec3,
+    /// BSDF times the cosine between `direction` and the normal
+    pub value: Vec3,
+    /// Density of picking `direction`, 1 for delta samples
+    pub pdf: f32,
+    /// Whether the direction comes from a delta distribution, like a mirror, that `eval` and
+    /// `pdf` can't reproduce
+    pub delta: bool,
+}
+
+/// In every method `ray` is the ray that hit the surface and `direction` points away from it,
+/// towards where light comes from
+pub trait Material: Send + Sync {
+    /// Scattered ray and its weight, `None` if the ray is absorbed
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3);
+
+    /// Light given off by the surface on top of what it scatters
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Whether `emitted` can be anything but zero, the surfaces that are sampled as lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// BSDF times the cosine between `direction` and the normal
+    fn eval(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Density of `sample` picking `direction`
+    fn pdf(&self, _ray: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Pick a direction to continue the path in, `None` if the ray is absorbed. Materials that
+    /// only implement `scatter` give delta samples weighted like `scatter`.
+    fn sample(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let (scattered, attenuation) = self.scatter(ray, hit_record, sampler);
+
+        scattered.map(|scattered| BsdfSample {
+            direction: scattered.direction,
+            value: attenuation,
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+}
+
+/// `scatter` for materials that implement `sample`
+fn scatter_sample(sample: Option<BsdfSample>, hit_record: &HitRecord) -> (Option<Ray>, Vec3) {
+    match sample {
+        Some(sample) => (
+            Some(Ray {
+                origin: hit_record.point,
+                direction: sample.direction,
+            }),
+            sample.value / sample.pdf,
+        ),
+        None => (None, Vec3::zero()),
+    }
+}
+
+/// Tangent space around a normal, where the normal is +z
+struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    fn new(normal: Vec3) -> Self {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    fn to_local(&self, direction: Vec3) -> Vec3 {
+        Vec3::from(
+            direction.dot(&self.tangent),
+            direction.dot(&self.bitangent),
+            direction.dot(&self.normal),
+        )
+    }
+
+    fn to_world(&self, direction: Vec3) -> Vec3 {
+        self.tangent * direction.x + self.bitangent * direction.y + self.normal * direction.z
+    }
 }
 
 pub struct PointLightMaterial {
@@ -58,9 +164,62 @@ pub struct PointLightMaterial {
 }
 
 impl Material for PointLightMaterial {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Option<Ray>, Vec3) {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3) {
         (None, self.color)
     }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        self.color
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Surface that only gives off light, like a lamp panel
+pub struct Emissive {
+    pub radiance: Vec3,
+    /// Whether the back of the surface glows too, otherwise only the side the outward normal
+    /// points to does
+    pub two_sided: bool,
+}
+
+impl Emissive {
+    pub fn boxed(radiance: Vec3, two_sided: bool) -> Box<dyn Material> {
+        Box::new(Self {
+            radiance,
+            two_sided,
+        })
+    }
+}
+
+impl Material for Emissive {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _hit_record: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3) {
+        (None, Vec3::zero())
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        if hit_record.front_face || self.two_sided {
+            self.radiance
+        } else {
+            Vec3::zero()
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.radiance != Vec3::zero()
+    }
 }
 
 pub struct Diffuse {
@@ -74,16 +233,615 @@ impl Diffuse {
 }
 
 impl Material for Diffuse {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> (Option<Ray>, Vec3) {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3) {
+        scatter_sample(self.sample(ray, hit_record, sampler), hit_record)
+    }
+
+    fn eval(&self, _ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        self.color * (direction.normalized().dot(&hit_record.normal).max(0.0) / PI)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f32 {
+        sampling::cosine_hemisphere_pdf(direction.normalized().dot(&hit_record.normal))
+    }
+
+    fn sample(
+        &self,
+        _ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let (local, pdf) = sampling::cosine_hemisphere(sampler.next_2d());
+
+        Some(BsdfSample {
+            direction: Frame::new(hit_record.normal).to_world(local),
+            value: self.color * (local.z / PI),
+            pdf,
+            delta: false,
+        })
+    }
+}
+
+pub struct Metal {
+    pub color: Vec3,
+    /// Perceptual roughness like `Pbr`'s, 0 is a perfect mirror
+    pub roughness: f32,
+}
+
+impl Metal {
+    pub fn boxed(color: Vec3, roughness: f32) -> Box<dyn Material> {
+        Box::new(Self { color, roughness })
+    }
+
+    /// A rough metal is a fully metallic GGX lobe, which can be evaluated. A perfect mirror
+    /// is a delta that only `scatter` can follow.
+    fn lobe(&self) -> Option<Pbr> {
+        (self.roughness > 0.0).then(|| Pbr {
+            base_color: self.color,
+            metallic: 1.0,
+            roughness: self.roughness,
+            specular: 0.5,
+            emission: Vec3::zero(),
+        })
+    }
+}
+
+impl Material for Metal {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3) {
+        if let Some(lobe) = self.lobe() {
+            return lobe.scatter(ray, hit_record, sampler);
+        }
+
+        let ray = Ray {
+            origin: hit_record.point,
+            direction: ray.reflect(hit_record.normal).normalized(),
+        };
+
+        (Some(ray), self.color)
+    }
+
+    fn eval(&self, ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        self.lobe()
+            .map_or(Vec3::zero(), |lobe| lobe.eval(ray, hit_record, direction))
+    }
+
+    fn pdf(&self, ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f32 {
+        self.lobe()
+            .map_or(0.0, |lobe| lobe.pdf(ray, hit_record, direction))
+    }
+
+    fn sample(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        match self.lobe() {
+            Some(lobe) => lobe.sample(ray, hit_record, sampler),
+            None => Some(BsdfSample {
+                direction: ray.reflect(hit_record.normal).normalized(),
+                value: self.color,
+                pdf: 1.0,
+                delta: true,
+            }),
+        }
+    }
+}
+
+/// Transparent material like glass or water
+pub struct Dielectric {
+    pub color: Vec3,
+    pub refractive_index: f32,
+}
+
+impl Dielectric {
+    pub fn boxed(color: Vec3, refractive_index: f32) -> Box<dyn Material> {
+        Box::new(Self { color, refractive_index })
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3) {
+        let eta_ratio = if hit_record.front_face {
+            1.0 / self.refractive_index
+        } else {
+            self.refractive_index
+        };
+
+        let cos_theta = (-ray.direction.normalized().dot(&hit_record.normal)).min(1.0);
+
+        let direction = match ray.refract(hit_record.normal, eta_ratio) {
+            Some(refracted) if schlick(cos_theta, eta_ratio) <= sampler.next_1d() => refracted,
+            // Total internal reflection, or reflected by chance
+            _ => ray.reflect(hit_record.normal),
+        };
+
         let ray = Ray {
             origin: hit_record.point,
-            direction: hit_record.normal + Vec3::random_unit_vector(),
+            direction,
         };
 
         (Some(ray), self.color)
     }
 }
 
-// Metal
-// Mirror
-// Mesh
\ No newline at end of file
+/// Schlick's approximation of the Fresnel reflectance
+fn schlick(cos_theta: f32, eta_ratio: f32) -> f32 {
+    let r0 = ((1.0 - eta_ratio) / (1.0 + eta_ratio)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+}
+
+/// Physically based material following glTF's metallic-roughness model, a GGX microfacet
+/// specular lobe over a Lambertian diffuse lobe
+pub struct Pbr {
+    pub base_color: Vec3,
+    /// 0 for dielectrics, 1 for metals, the base colour tints the reflections of metals
+    pub metallic: f32,
+    /// Perceptual roughness, squared to get the GGX alpha
+    pub roughness: f32,
+    /// Reflectance of dielectrics at normal incidence, 0.5 gives the usual 4%
+    pub specular: f32,
+    pub emission: Vec3,
+}
+
+impl Pbr {
+    fn alpha(&self) -> f32 {
+        // A perfectly smooth surface makes the distribution a delta, which can't be evaluated
+        (self.roughness * self.roughness).max(1e-3)
+    }
+
+    /// Fresnel reflectance at normal incidence
+    fn f0(&self) -> Vec3 {
+        Vec3::one() * 0.08 * self.specular * (1.0 - self.metallic) + self.base_color * self.metallic
+    }
+
+    fn fresnel(&self, cos_theta: f32) -> Vec3 {
+        let f0 = self.f0();
+        f0 + (Vec3::one() - f0) * (1.0 - cos_theta).max(0.0).powi(5)
+    }
+
+    /// Chance of sampling the specular lobe instead of the diffuse one, following how much
+    /// each reflects
+    fn specular_probability(&self, n_dot_v: f32) -> f32 {
+        let specular = luminance(self.fresnel(n_dot_v));
+        let diffuse = luminance(self.base_color) * (1.0 - self.metallic) * (1.0 - specular);
+
+        if diffuse <= 0.0 {
+            return 1.0;
+        }
+
+        (specular / (specular + diffuse)).clamp(0.1, 1.0)
+    }
+
+    /// BSDF times the cosine of the outgoing direction, `v` towards the viewer and `l` towards
+    /// the light in a frame where the normal is +z
+    fn eval_local(&self, v: Vec3, l: Vec3) -> Vec3 {
+        if v.z <= 0.0 || l.z <= 0.0 {
+            return Vec3::zero();
+        }
+
+        let alpha = self.alpha();
+        let h = (v + l).normalized();
+        let fresnel = self.fresnel(v.dot(&h));
+
+        let specular = fresnel * (ggx_d(h.z, alpha) * smith_g2(v.z, l.z, alpha) / (4.0 * v.z * l.z));
+        // glTF weights the diffuse lobe by the Fresnel term of the half vector, which reflects
+        // more than it receives at grazing angles, the view's Fresnel term doesn't
+        let diffuse =
+            (Vec3::one() - self.fresnel(v.z)) * self.base_color * ((1.0 - self.metallic) / PI);
+
+        (specular + diffuse) * l.z
+    }
+
+    /// Density of `sample_local` choosing `l`
+    fn pdf_local(&self, v: Vec3, l: Vec3) -> f32 {
+        if v.z <= 0.0 || l.z <= 0.0 {
+            return 0.0;
+        }
+
+        let alpha = self.alpha();
+        let h = (v + l).normalized();
+        // Visible normal density, D_v(h) / (4 v.h) with D_v(h) = G1(v) D(h) v.h / v.z
+        let specular = smith_g1(v.z, alpha) * ggx_d(h.z, alpha) / (4.0 * v.z);
+        let diffuse = l.z / PI;
+        let probability = self.specular_probability(v.z);
+
+        probability * specular + (1.0 - probability) * diffuse
+    }
+
+    fn sample_local(&self, v: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        if sampler.next_1d() < self.specular_probability(v.z) {
+            let h = sample_ggx_vndf(v, self.alpha(), sampler.next_2d());
+            h * (2.0 * v.dot(&h)) - v
+        } else {
+            sampling::cosine_hemisphere(sampler.next_2d()).0
+        }
+    }
+}
+
+impl Material for Pbr {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, Vec3) {
+        scatter_sample(self.sample(ray, hit_record, sampler), hit_record)
+    }
+
+    fn eval(&self, ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let frame = Frame::new(hit_record.normal);
+        self.eval_local(
+            frame.to_local(-ray.direction.normalized()),
+            frame.to_local(direction.normalized()),
+        )
+    }
+
+    fn pdf(&self, ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f32 {
+        let frame = Frame::new(hit_record.normal);
+        self.pdf_local(
+            frame.to_local(-ray.direction.normalized()),
+            frame.to_local(direction.normalized()),
+        )
+    }
+
+    fn sample(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let frame = Frame::new(hit_record.normal);
+        let v = frame.to_local(-ray.direction.normalized());
+        let l = self.sample_local(v, sampler);
+        let pdf = self.pdf_local(v, l);
+
+        // Samples below the surface, and a view from below, are absorbed
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            direction: frame.to_world(l),
+            value: self.eval_local(v, l),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        self.emission
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission != Vec3::zero()
+    }
+}
+
+pub fn luminance(color: Vec3) -> f32 {
+    color.dot(&Vec3::from(0.2126, 0.7152, 0.0722))
+}
+
+/// GGX normal distribution
+fn ggx_d(cos_theta: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let denominator = cos_theta * cos_theta * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denominator * denominator)
+}
+
+/// Smith masking for one direction
+fn smith_g1(cos_theta: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    2.0 * cos_theta / (cos_theta + (alpha2 + (1.0 - alpha2) * cos_theta * cos_theta).sqrt())
+}
+
+/// Height-correlated Smith masking and shadowing
+fn smith_g2(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let v = n_dot_l * (alpha2 + (1.0 - alpha2) * n_dot_v * n_dot_v).sqrt();
+    let l = n_dot_v * (alpha2 + (1.0 - alpha2) * n_dot_l * n_dot_l).sqrt();
+    2.0 * n_dot_v * n_dot_l / (v + l)
+}
+
+/// Microfacet normal seen from `v`, Heitz, "Sampling the GGX Distribution of Visible Normals"
+fn sample_ggx_vndf(v: Vec3, alpha: f32, u: [f32; 2]) -> Vec3 {
+    // Stretch the view so the distribution becomes a hemisphere
+    let vh = Vec3::from(alpha * v.x, alpha * v.y, v.z).normalized();
+    let length_squared = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if length_squared > 0.0 {
+        Vec3::from(-vh.y, vh.x, 0.0) / length_squared.sqrt()
+    } else {
+        Vec3::from(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(&t1);
+
+    // Uniform point on a disk, squashed onto the part of the hemisphere that is visible
+    let r = u[0].sqrt();
+    let phi = TAU * u[1];
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    Vec3::from(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalized()
+}
+
+// Mesh
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerKind;
+
+    const ROUGHNESS: [f32; 5] = [0.05, 0.25, 0.5, 0.75, 1.0];
+    /// Cosines between the view and the normal, from straight on to grazing
+    const VIEWS: [f32; 4] = [1.0, 0.7, 0.3, 0.05];
+
+    fn white(metallic: f32, roughness: f32) -> Pbr {
+        Pbr {
+            base_color: Vec3::one(),
+            metallic,
+            roughness,
+            specular: 0.5,
+            emission: Vec3::zero(),
+        }
+    }
+
+    /// Ray hitting a surface at the origin facing +z, `cos_theta` from the normal
+    fn view(cos_theta: f32) -> (Ray, HitRecord) {
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let ray = Ray {
+            origin: Vec3::from(sin_theta, 0.0, cos_theta),
+            direction: Vec3::from(-sin_theta, 0.0, -cos_theta),
+        };
+        let hit_record = HitRecord::new(&ray, Vec3::zero(), Vec3::from(0.0, 0.0, 1.0), 1.0, 0);
+        (ray, hit_record)
+    }
+
+    /// Samples of `material` seen from `cos_theta`, `None` for absorbed ones
+    fn samples(material: &dyn Material, cos_theta: f32, count: u32) -> Vec<Option<BsdfSample>> {
+        let (ray, hit_record) = view(cos_theta);
+        let mut sampler = SamplerKind::Sobol.create(7, count);
+
+        (0..count)
+            .map(|index| {
+                sampler.start(0, index);
+                material.sample(&ray, &hit_record, sampler.as_mut())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn white_furnace_keeps_at_most_the_light_it_receives() {
+        let count = 1 << 14;
+
+        for metallic in [0.0, 1.0] {
+            for roughness in ROUGHNESS {
+                for cos_theta in VIEWS {
+                    let material = white(metallic, roughness);
+                    let albedo = samples(&material, cos_theta, count)
+                        .iter()
+                        .flatten()
+                        .fold(Vec3::zero(), |total, sample| {
+                            total + sample.value / sample.pdf
+                        })
+                        / count as f32;
+
+                    for channel in [albedo.x, albedo.y, albedo.z] {
+                        assert!(
+                            channel <= 1.01,
+                            "metallic {} roughness {} cos {} reflects {}",
+                            metallic,
+                            roughness,
+                            cos_theta,
+                            channel
+                        );
+                        // Single scattering GGX loses energy at high roughness, a rough metal
+                        // seen head on keeps 1 - ln 2
+                        assert!(
+                            channel > 0.25,
+                            "metallic {} roughness {} cos {} reflects {}",
+                            metallic,
+                            roughness,
+                            cos_theta,
+                            channel
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn eval_and_pdf_match_sample() {
+        // Smoother lobes are so narrow that finding the half vector again in f32 moves the
+        // density by a few percent
+        let materials = ROUGHNESS[1..].iter().flat_map(|&roughness| {
+            [0.0, 0.5, 1.0].map(|metallic| Pbr {
+                base_color: Vec3::from(0.9, 0.5, 0.2),
+                ..white(metallic, roughness)
+            })
+        });
+
+        for material in materials {
+            for cos_theta in VIEWS {
+                let (ray, hit_record) = view(cos_theta);
+
+                for sample in samples(&material, cos_theta, 256).iter().flatten() {
+                    let value = material.eval(&ray, &hit_record, sample.direction);
+                    let pdf = material.pdf(&ray, &hit_record, sample.direction);
+
+                    assert!(!sample.delta);
+                    assert!(
+                        (pdf - sample.pdf).abs() <= 1e-3 * sample.pdf,
+                        "{} != {}",
+                        pdf,
+                        sample.pdf
+                    );
+                    assert!(
+                        (value - sample.value).length() <= 1e-3 * sample.value.length(),
+                        "{:?} != {:?}",
+                        value,
+                        sample.value
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_bends_light_with_snells_law() {
+        let glass = 1.5;
+
+        for cos_theta in VIEWS {
+            let (ray, hit_record) = view(cos_theta);
+            let refracted = ray
+                .refract(hit_record.normal, 1.0 / glass)
+                .unwrap()
+                .normalized();
+            let sin_in = (1.0 - cos_theta * cos_theta).sqrt();
+            let sin_out = (1.0 - refracted.z * refracted.z).sqrt();
+
+            // Into the surface, on the other side of the normal and in the plane of incidence
+            assert!(refracted.z < 0.0);
+            assert!(refracted.x <= 0.0 && refracted.y == 0.0);
+            assert!(
+                (sin_in - glass * sin_out).abs() < 1e-4,
+                "{} at {}",
+                sin_out,
+                cos_theta
+            );
+        }
+    }
+
+    #[test]
+    fn dielectric_reflects_everything_past_the_critical_angle() {
+        let glass = Dielectric {
+            color: Vec3::one(),
+            refractive_index: 1.5,
+        };
+        let critical = (1.0 / glass.refractive_index).asin();
+        let mut sampler = SamplerKind::Independent.create(3, 64);
+
+        for (angle, total) in [(critical - 0.01, false), (critical + 0.01, true)] {
+            // Leaving the glass, the outward normal points away from the side the ray is on
+            let ray = Ray {
+                origin: Vec3::from(angle.sin(), 0.0, angle.cos()),
+                direction: Vec3::from(-angle.sin(), 0.0, -angle.cos()),
+            };
+            let hit_record = HitRecord::new(&ray, Vec3::zero(), Vec3::from(0.0, 0.0, -1.0), 1.0, 0);
+            assert!(!hit_record.front_face);
+
+            let reflected = ray.reflect(hit_record.normal);
+            let reflections = (0..64)
+                .filter(|&index| {
+                    sampler.start(0, index);
+                    let (scattered, _) = glass.scatter(&ray, &hit_record, sampler.as_mut());
+                    scattered.unwrap().direction == reflected
+                })
+                .count();
+
+            assert_eq!(
+                ray.refract(hit_record.normal, glass.refractive_index)
+                    .is_none(),
+                total
+            );
+            if total {
+                assert_eq!(reflections, 64);
+            } else {
+                assert!(reflections < 64);
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_reflects_4_percent_head_on() {
+        // ((1 - 1.5) / (1 + 1.5))^2 from either side, and everything at grazing angles
+        assert!((schlick(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert!((schlick(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(schlick(0.0, 1.0 / 1.5), 1.0);
+
+        let glass = Dielectric {
+            color: Vec3::one(),
+            refractive_index: 1.5,
+        };
+        let (ray, hit_record) = view(1.0);
+        let count = 4096;
+        let mut sampler = SamplerKind::Sobol.create(7, count);
+        let reflections = (0..count)
+            .filter(|&index| {
+                sampler.start(0, index);
+                let (scattered, _) = glass.scatter(&ray, &hit_record, sampler.as_mut());
+                scattered.unwrap().direction.z > 0.0
+            })
+            .count();
+
+        let fraction = reflections as f32 / count as f32;
+        assert!((fraction - 0.04).abs() < 0.005, "{}", fraction);
+    }
+
+    #[test]
+    fn rough_metal_evaluates_like_a_metallic_pbr() {
+        let color = Vec3::from(0.9, 0.5, 0.2);
+
+        for &roughness in &ROUGHNESS[1..] {
+            let metal = Metal { color, roughness };
+            let pbr = Pbr {
+                base_color: color,
+                ..white(1.0, roughness)
+            };
+
+            for cos_theta in VIEWS {
+                let (ray, hit_record) = view(cos_theta);
+
+                for sample in samples(&metal, cos_theta, 64).iter().flatten() {
+                    let direction = sample.direction;
+                    assert!(!sample.delta);
+                    let value = metal.eval(&ray, &hit_record, direction);
+                    let pdf = metal.pdf(&ray, &hit_record, direction);
+
+                    assert_eq!(value, pbr.eval(&ray, &hit_record, direction));
+                    assert_eq!(pdf, pbr.pdf(&ray, &hit_record, direction));
+                    assert!((pdf - sample.pdf).abs() <= 1e-3 * sample.pdf);
+                    assert!((value - sample.value).length() <= 1e-3 * sample.value.length());
+                }
+            }
+        }
+
+        // A perfect mirror stays a delta that light sampling can't hit
+        let mirror = Metal {
+            color,
+            roughness: 0.0,
+        };
+        let (ray, hit_record) = view(0.7);
+        for sample in samples(&mirror, 0.7, 4).iter() {
+            let sample = sample.unwrap();
+            assert!(sample.delta);
+            assert_eq!(sample.value, color);
+            assert_eq!(
+                sample.direction,
+                ray.reflect(hit_record.normal).normalized()
+            );
+            assert_eq!(
+                mirror.eval(&ray, &hit_record, sample.direction),
+                Vec3::zero()
+            );
+            assert_eq!(mirror.pdf(&ray, &hit_record, sample.direction), 0.0);
+        }
+    }
+}
@@ -1,4 +1,7 @@
-#[derive(Clone, Copy, Debug, PartialEq)]
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(from = "[f32; 3]")]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -55,12 +58,59 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Self {
-        Vec3 {
-            x: rand::random::<f32>() * 2.0 - 1.0,
-            y: rand::random::<f32>() * 2.0 - 1.0,
-            z: rand::random::<f32>() * 2.0 - 1.0,
-        }.normalized()
+    /// Component-wise minimum
+    pub fn min(&self, other: &Self) -> Self {
+        Self {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+
+    /// Component-wise maximum
+    pub fn max(&self, other: &Self) -> Self {
+        Self {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
+    /// Two unit vectors that together with this one, which must be normalized, form an
+    /// orthonormal basis. Duff et al., "Building an Orthonormal Basis, Revisited".
+    pub fn orthonormal_basis(&self) -> (Self, Self) {
+        let sign = 1f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+
+        (
+            Self::from(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Self::from(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
+
+    /// Uniformly distributed direction
+    pub fn random_unit_vector(rng: &mut crate::random::Rng) -> Self {
+        crate::sampling::uniform_sphere(rng.next_2d()).0
+    }
+}
+
+impl From<[f32; 3]> for Vec3 {
+    fn from([x, y, z]: [f32; 3]) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl std::ops::Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, axis: usize) -> &f32 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis out of range: {}", axis),
+        }
     }
 }
 
@@ -206,4 +256,836 @@ impl std::ops::Neg for Vec3 {
             z: -self.z 
         }
     }
-}
\ No newline at end of file
+}
+/// Row-major 3x3 matrix for rotations and scales, vectors are columns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    pub rows: [[f32; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn identity() -> Self {
+        Self {
+            rows: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Matrix that takes the x, y and z axes to `x`, `y` and `z`
+    pub fn from_columns(x: Vec3, y: Vec3, z: Vec3) -> Self {
+        Self {
+            rows: [[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]],
+        }
+    }
+
+    pub fn scale(scale: Vec3) -> Self {
+        Self {
+            rows: [
+                [scale.x, 0.0, 0.0],
+                [0.0, scale.y, 0.0],
+                [0.0, 0.0, scale.z],
+            ],
+        }
+    }
+
+    /// Rotation by `angle` radians around the x axis
+    pub fn rotation_x(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self {
+            rows: [[1.0, 0.0, 0.0], [0.0, cos, -sin], [0.0, sin, cos]],
+        }
+    }
+
+    /// Rotation by `angle` radians around the y axis
+    pub fn rotation_y(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self {
+            rows: [[cos, 0.0, sin], [0.0, 1.0, 0.0], [-sin, 0.0, cos]],
+        }
+    }
+
+    /// Rotation by `angle` radians around the z axis
+    pub fn rotation_z(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self {
+            rows: [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn column(&self, index: usize) -> Vec3 {
+        Vec3::from(
+            self.rows[0][index],
+            self.rows[1][index],
+            self.rows[2][index],
+        )
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self::from_columns(
+            Vec3::from(self.rows[0][0], self.rows[0][1], self.rows[0][2]),
+            Vec3::from(self.rows[1][0], self.rows[1][1], self.rows[1][2]),
+            Vec3::from(self.rows[2][0], self.rows[2][1], self.rows[2][2]),
+        )
+    }
+
+    pub fn determinant(&self) -> f32 {
+        self.column(0).dot(&self.column(1).cross(&self.column(2)))
+    }
+
+    /// `None` if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let determinant = self.determinant();
+
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        // The rows of the inverse are the cross products of the columns
+        let (x, y, z) = (self.column(0), self.column(1), self.column(2));
+        Some(
+            Self::from_columns(y.cross(&z), z.cross(&x), x.cross(&y)).transpose()
+                * (1.0 / determinant),
+        )
+    }
+}
+
+impl std::ops::Mul for Mat3 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            rows: std::array::from_fn(|row| {
+                std::array::from_fn(|column| {
+                    (0..3)
+                        .map(|i| self.rows[row][i] * other.rows[i][column])
+                        .sum()
+                })
+            }),
+        }
+    }
+}
+
+impl std::ops::Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+
+    fn mul(self, vector: Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3 {
+            x: m[0][0] * vector.x + m[0][1] * vector.y + m[0][2] * vector.z,
+            y: m[1][0] * vector.x + m[1][1] * vector.y + m[1][2] * vector.z,
+            z: m[2][0] * vector.x + m[2][1] * vector.y + m[2][2] * vector.z,
+        }
+    }
+}
+
+impl std::ops::Mul<f32> for Mat3 {
+    type Output = Self;
+
+    fn mul(self, scalar: f32) -> Self {
+        Self {
+            rows: self.rows.map(|row| row.map(|x| x * scalar)),
+        }
+    }
+}
+
+/// Row-major 4x4 matrix for affine transforms and projections, points are columns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub rows: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        Self::from_mat3(Mat3::identity())
+    }
+
+    /// Rotation and scale from `matrix` without a translation
+    pub fn from_mat3(matrix: Mat3) -> Self {
+        let m = &matrix.rows;
+        Self {
+            rows: [
+                [m[0][0], m[0][1], m[0][2], 0.0],
+                [m[1][0], m[1][1], m[1][2], 0.0],
+                [m[2][0], m[2][1], m[2][2], 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut matrix = Self::identity();
+        matrix.rows[0][3] = offset.x;
+        matrix.rows[1][3] = offset.y;
+        matrix.rows[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scale(scale: Vec3) -> Self {
+        Self::from_mat3(Mat3::scale(scale))
+    }
+
+    pub fn rotation(rotation: Quat) -> Self {
+        Self::from_mat3(rotation.to_mat3())
+    }
+
+    /// Scale first, then rotate, then translate
+    pub fn from_scale_rotation_translation(scale: Vec3, rotation: Quat, translation: Vec3) -> Self {
+        Self::translation(translation) * Self::rotation(rotation) * Self::scale(scale)
+    }
+
+    /// Camera to world matrix for a camera at `eye` looking at `target`. The camera looks down
+    /// its +z axis with +y up and +x to the right, like the renderer's camera.
+    /// `None` if `eye` and `target` are the same or `up` is parallel to the view direction.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Option<Self> {
+        let forward = target - eye;
+        let right = up.cross(&forward);
+
+        if forward.length() < 1e-12 || right.length() < 1e-12 * forward.length() {
+            return None;
+        }
+
+        let forward = forward.normalized();
+        let right = right.normalized();
+        let up = forward.cross(&right);
+
+        let mut matrix = Self::from_mat3(Mat3::from_columns(right, up, forward));
+        matrix.rows[0][3] = eye.x;
+        matrix.rows[1][3] = eye.y;
+        matrix.rows[2][3] = eye.z;
+        Some(matrix)
+    }
+
+    /// Projection for a camera looking down +z, `fov_y` in radians. Points between `near` and
+    /// `far` get a depth from 0 to 1 after `project_point`.
+    pub fn perspective(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Self {
+        let y = 1.0 / (fov_y * 0.5).tan();
+        let x = y / aspect_ratio;
+        let depth = far / (far - near);
+
+        Self {
+            rows: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, depth, -near * depth],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+        }
+    }
+
+    /// Upper left 3x3, the rotation and scale of an affine transform
+    pub fn to_mat3(self) -> Mat3 {
+        Mat3 {
+            rows: std::array::from_fn(|row| std::array::from_fn(|column| self.rows[row][column])),
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self {
+            rows: std::array::from_fn(|row| std::array::from_fn(|column| self.rows[column][row])),
+        }
+    }
+
+    /// Inverse through Gauss-Jordan elimination, `None` if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut left = self.rows;
+        let mut right = Self::identity().rows;
+
+        for column in 0..4 {
+            // Partial pivoting keeps the elimination stable
+            let pivot = (column..4)
+                .max_by(|&a, &b| left[a][column].abs().total_cmp(&left[b][column].abs()))?;
+
+            if left[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+
+            left.swap(column, pivot);
+            right.swap(column, pivot);
+
+            let scale = 1.0 / left[column][column];
+            for i in 0..4 {
+                left[column][i] *= scale;
+                right[column][i] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let factor = left[row][column];
+                for i in 0..4 {
+                    left[row][i] -= factor * left[column][i];
+                    right[row][i] -= factor * right[column][i];
+                }
+            }
+        }
+
+        Some(Self { rows: right })
+    }
+
+    /// Transform a point, including the translation
+    pub fn transform_point(&self, point: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3 {
+            x: m[0][0] * point.x + m[0][1] * point.y + m[0][2] * point.z + m[0][3],
+            y: m[1][0] * point.x + m[1][1] * point.y + m[1][2] * point.z + m[1][3],
+            z: m[2][0] * point.x + m[2][1] * point.y + m[2][2] * point.z + m[2][3],
+        }
+    }
+
+    /// Transform a point and divide by w, for projections
+    pub fn project_point(&self, point: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        let w = m[3][0] * point.x + m[3][1] * point.y + m[3][2] * point.z + m[3][3];
+        self.transform_point(point) / w
+    }
+
+    /// Transform a direction, ignoring the translation
+    pub fn transform_vector(&self, vector: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3 {
+            x: m[0][0] * vector.x + m[0][1] * vector.y + m[0][2] * vector.z,
+            y: m[1][0] * vector.x + m[1][1] * vector.y + m[1][2] * vector.z,
+            z: m[2][0] * vector.x + m[2][1] * vector.y + m[2][2] * vector.z,
+        }
+    }
+
+    /// Multiply by the transpose, on the inverse of a transform this takes object space
+    /// normals to world space so they stay perpendicular under non-uniform scale. The result
+    /// is not normalized.
+    pub fn transform_normal(&self, normal: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3 {
+            x: m[0][0] * normal.x + m[1][0] * normal.y + m[2][0] * normal.z,
+            y: m[0][1] * normal.x + m[1][1] * normal.y + m[2][1] * normal.z,
+            z: m[0][2] * normal.x + m[1][2] * normal.y + m[2][2] * normal.z,
+        }
+    }
+}
+
+impl std::ops::Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            rows: std::array::from_fn(|row| {
+                std::array::from_fn(|column| {
+                    (0..4)
+                        .map(|i| self.rows[row][i] * other.rows[i][column])
+                        .sum()
+                })
+            }),
+        }
+    }
+}
+
+/// Rotation quaternion, `w` is the real part
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub fn identity() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        }
+    }
+
+    /// Rotation by `angle` radians around `axis`, which doesn't have to be normalized
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+        let (sin, cos) = (angle * 0.5).sin_cos();
+        let axis = axis.normalized() * sin;
+        Self {
+            x: axis.x,
+            y: axis.y,
+            z: axis.z,
+            w: cos,
+        }
+    }
+
+    /// Axis and angle in radians, the axis is x for the identity
+    pub fn to_axis_angle(self) -> (Vec3, f32) {
+        let q = self.normalized();
+        let sin = (1.0 - q.w * q.w).max(0.0).sqrt();
+
+        if sin < 1e-6 {
+            return (Vec3::from(1.0, 0.0, 0.0), 0.0);
+        }
+
+        (
+            Vec3::from(q.x, q.y, q.z) / sin,
+            2.0 * q.w.clamp(-1.0, 1.0).acos(),
+        )
+    }
+
+    /// Euler angles in radians, rotating around x first, then y, then z
+    pub fn from_euler(angles: Vec3) -> Self {
+        Self::from_axis_angle(Vec3::from(0.0, 0.0, 1.0), angles.z)
+            * Self::from_axis_angle(Vec3::from(0.0, 1.0, 0.0), angles.y)
+            * Self::from_axis_angle(Vec3::from(1.0, 0.0, 0.0), angles.x)
+    }
+
+    /// Inverse of `from_euler`, with y in -pi/2..=pi/2
+    pub fn to_euler(self) -> Vec3 {
+        let m = self.to_mat3().rows;
+        let y = (-m[2][0]).clamp(-1.0, 1.0).asin();
+
+        // Looking straight along y, x and z rotate around the same axis so put it all in z
+        if m[2][0].abs() > 0.99999 {
+            return Vec3::from(0.0, y, (-m[0][1]).atan2(m[1][1]));
+        }
+
+        Vec3::from(m[2][1].atan2(m[2][2]), y, m[1][0].atan2(m[0][0]))
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        let Self { x, y, z, w } = self;
+        Mat3 {
+            rows: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+        }
+    }
+
+    pub fn dot(&self, other: &Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Self {
+        let length = self.length();
+        Self {
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+            w: self.w / length,
+        }
+    }
+
+    /// Opposite rotation, the same as the inverse for a normalized quaternion
+    pub fn conjugate(&self) -> Self {
+        Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+            w: self.w,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        let length_squared = self.dot(self);
+        let conjugate = self.conjugate();
+        Self {
+            x: conjugate.x / length_squared,
+            y: conjugate.y / length_squared,
+            z: conjugate.z / length_squared,
+            w: conjugate.w / length_squared,
+        }
+    }
+
+    /// Rotate `vector`, the quaternion should be normalized
+    pub fn rotate(&self, vector: &Vec3) -> Vec3 {
+        let axis = Vec3::from(self.x, self.y, self.z);
+        let t = axis.cross(vector) * 2.0;
+        *vector + t * self.w + axis.cross(&t)
+    }
+
+    /// Interpolate along the shortest arc, `t` = 0 gives `self` and 1 gives `other`
+    pub fn slerp(&self, other: &Self, t: f32) -> Self {
+        let mut other = *other;
+        let mut cos = self.dot(&other);
+
+        // q and -q are the same rotation, take the one closer to self
+        if cos < 0.0 {
+            other = Self {
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+                w: -other.w,
+            };
+            cos = -cos;
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            // Nearly the same rotation, sin(angle) is too small to divide by
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+
+        Self {
+            x: self.x * a + other.x * b,
+            y: self.y * a + other.y * b,
+            z: self.z * a + other.z * b,
+            w: self.w * a + other.w * b,
+        }
+        .normalized()
+    }
+}
+
+/// Composition, `a * b` rotates by `b` first and then by `a`
+impl std::ops::Mul for Quat {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_identity(matrix: Mat4) {
+        let identity = Mat4::identity();
+        for row in 0..4 {
+            for column in 0..4 {
+                let difference = matrix.rows[row][column] - identity.rows[row][column];
+                assert!(difference.abs() < 1e-4, "not the identity: {:?}", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let skewed = Mat4 {
+            rows: [
+                [2.0, 0.5, 0.0, 1.0],
+                [0.0, 1.0, -3.0, 2.0],
+                [1.0, 0.0, 0.5, -4.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        };
+        let matrices = [
+            Mat4::identity(),
+            skewed,
+            Mat4::from_scale_rotation_translation(
+                Vec3::from(2.0, 0.5, 3.0),
+                Quat::from_euler(Vec3::from(0.3, -1.2, 2.0)),
+                Vec3::from(1.0, -2.0, 5.0),
+            ),
+            Mat4::look_at(
+                Vec3::from(1.0, 2.0, 3.0),
+                Vec3::zero(),
+                Vec3::from(0.0, 1.0, 0.0),
+            )
+            .unwrap(),
+        ];
+
+        for matrix in matrices {
+            let inverse = matrix.inverse().unwrap();
+            assert_identity(matrix * inverse);
+            assert_identity(inverse * matrix);
+        }
+
+        let flat = Mat4::from_scale_rotation_translation(
+            Vec3::from(1.0, 0.0, 1.0),
+            Quat::from_euler(Vec3::from(0.3, 0.2, 0.1)),
+            Vec3::zero(),
+        );
+        assert!(flat.inverse().is_none());
+    }
+
+    #[test]
+    fn euler_angles_round_trip() {
+        let steps = [-2.5, -1.0, -0.2, 0.0, 0.7, 1.5, 3.0];
+
+        for x in steps {
+            for y in steps.map(|y| y * 0.5) {
+                for z in steps {
+                    let angles = Vec3::from(x, y, z);
+                    assert_close(Quat::from_euler(angles).to_euler(), angles);
+                }
+            }
+        }
+
+        // With y at 90 degrees only x - z matters, the angles differ but the rotation doesn't
+        let locked = Quat::from_euler(Vec3::from(0.4, FRAC_PI_2, 1.1));
+        let again = Quat::from_euler(locked.to_euler());
+        for axis in [
+            Vec3::from(1.0, 0.0, 0.0),
+            Vec3::from(0.0, 1.0, 0.0),
+            Vec3::from(0.0, 0.0, 1.0),
+        ] {
+            assert_close(again.rotate(&axis), locked.rotate(&axis));
+        }
+    }
+
+    #[test]
+    fn euler_angles_apply_x_then_y_then_z() {
+        let angles = Vec3::from(0.5, -0.8, 1.3);
+        let vector = Vec3::from(0.2, -1.0, 0.6);
+        let by_axis = [
+            (Vec3::from(1.0, 0.0, 0.0), angles.x),
+            (Vec3::from(0.0, 1.0, 0.0), angles.y),
+            (Vec3::from(0.0, 0.0, 1.0), angles.z),
+        ]
+        .iter()
+        .fold(vector, |vector, &(axis, angle)| {
+            Quat::from_axis_angle(axis, angle).rotate(&vector)
+        });
+
+        assert_close(Quat::from_euler(angles).rotate(&vector), by_axis);
+        assert_close(Quat::from_euler(angles).to_mat3() * vector, by_axis);
+    }
+
+    #[test]
+    fn axis_angle_round_trip() {
+        let axes = [
+            Vec3::from(1.0, 0.0, 0.0),
+            Vec3::from(0.0, -1.0, 0.0),
+            Vec3::from(1.0, 2.0, -3.0),
+            Vec3::from(-0.5, 0.1, 0.2),
+        ];
+
+        for axis in axes {
+            for angle in [0.1, 1.0, FRAC_PI_2, 3.0] {
+                let (result_axis, result_angle) =
+                    Quat::from_axis_angle(axis, angle).to_axis_angle();
+                assert_close(result_axis, axis.normalized());
+                assert!(
+                    (result_angle - angle).abs() < 1e-4,
+                    "{} != {}",
+                    result_angle,
+                    angle
+                );
+            }
+        }
+
+        let (_, angle) = Quat::from_axis_angle(Vec3::from(0.0, 1.0, 0.0), 0.0).to_axis_angle();
+        assert_eq!(angle, 0.0);
+    }
+
+    #[test]
+    fn look_at_faces_the_target() {
+        let eye = Vec3::from(1.0, 2.0, -3.0);
+        let target = Vec3::from(-2.0, 0.5, 4.0);
+        let up = Vec3::from(0.0, 1.0, 0.0);
+        let matrix = Mat4::look_at(eye, target, up).unwrap();
+
+        let forward = matrix.transform_vector(&Vec3::from(0.0, 0.0, 1.0));
+        let right = matrix.transform_vector(&Vec3::from(1.0, 0.0, 0.0));
+        let camera_up = matrix.transform_vector(&Vec3::from(0.0, 1.0, 0.0));
+
+        assert_close(matrix.transform_point(&Vec3::zero()), eye);
+        assert_close(forward, (target - eye).normalized());
+        assert_close(right, up.cross(&forward).normalized());
+        assert_close(camera_up, forward.cross(&right));
+        assert!(camera_up.dot(&up) > 0.0);
+        assert!((matrix.to_mat3().determinant() - 1.0).abs() < 1e-4);
+
+        assert!(Mat4::look_at(eye, eye, up).is_none());
+        assert!(Mat4::look_at(eye, eye + up * 2.0, up).is_none());
+    }
+
+    #[test]
+    fn slerp_ends_at_both_rotations() {
+        let a = Quat::from_euler(Vec3::from(0.3, 1.0, -0.5));
+        let b = Quat::from_euler(Vec3::from(-1.2, 0.2, 2.5));
+        // The same rotation as b from the other side of the sphere, slerp should still take the
+        // short way
+        let negated_b = Quat {
+            x: -b.x,
+            y: -b.y,
+            z: -b.z,
+            w: -b.w,
+        };
+        let vector = Vec3::from(0.3, -0.4, 0.9);
+
+        for other in [b, negated_b] {
+            assert_close(a.slerp(&other, 0.0).rotate(&vector), a.rotate(&vector));
+            assert_close(a.slerp(&other, 1.0).rotate(&vector), b.rotate(&vector));
+        }
+
+        // Halfway between no rotation and a quarter turn is an eighth turn
+        let z = Vec3::from(0.0, 0.0, 1.0);
+        let none = Quat::from_axis_angle(z, 0.0);
+        let quarter = Quat::from_axis_angle(z, FRAC_PI_2);
+        let (axis, angle) = none.slerp(&quarter, 0.5).to_axis_angle();
+        assert_close(axis, z);
+        assert!((angle - PI / 4.0).abs() < 1e-4);
+
+        // Nearly equal rotations blend linearly, which should still land in the middle
+        let (_, angle) = none
+            .slerp(&Quat::from_axis_angle(z, 0.01), 0.5)
+            .to_axis_angle();
+        assert!((angle - 0.005).abs() < 1e-4);
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let matrix = Mat4::from_scale_rotation_translation(
+            Vec3::from(4.0, 0.5, 1.0),
+            Quat::from_euler(Vec3::from(0.3, -0.7, 1.1)),
+            Vec3::from(2.0, 1.0, -1.0),
+        );
+        let inverse = matrix.inverse().unwrap();
+
+        // A tilted plane through the origin and two directions along it
+        let normal = Vec3::from(1.0, 1.0, 1.0).normalized();
+        let tangents = [Vec3::from(1.0, -1.0, 0.0), Vec3::from(0.0, 1.0, -1.0)];
+
+        let world_normal = inverse.transform_normal(&normal).normalized();
+        for tangent in tangents {
+            let world_tangent = matrix.transform_vector(&tangent);
+            assert!(world_normal.dot(&world_tangent).abs() < 1e-4);
+        }
+
+        // Transforming it like a direction would tilt it off the plane
+        let wrong = matrix.transform_vector(&normal);
+        assert!(wrong.dot(&matrix.transform_vector(&tangents[0])).abs() > 0.1);
+    }
+
+    #[test]
+    fn axis_rotations_match_the_quaternions() {
+        let vector = Vec3::from(0.3, -0.4, 0.9);
+        let axes = [
+            Vec3::from(1.0, 0.0, 0.0),
+            Vec3::from(0.0, 1.0, 0.0),
+            Vec3::from(0.0, 0.0, 1.0),
+        ];
+
+        for angle in [-2.0, 0.0, 0.5, FRAC_PI_2, 3.0] {
+            let matrices = [
+                Mat3::rotation_x(angle),
+                Mat3::rotation_y(angle),
+                Mat3::rotation_z(angle),
+            ];
+
+            for (matrix, axis) in matrices.into_iter().zip(axes) {
+                let rotation = Quat::from_axis_angle(axis, angle);
+                assert_close(matrix * vector, rotation.rotate(&vector));
+                assert_close(
+                    Mat4::rotation(rotation).transform_vector(&vector),
+                    rotation.rotate(&vector),
+                );
+            }
+        }
+
+        assert_close(Quat::identity().rotate(&vector), vector);
+        assert_identity(Mat4::rotation(Quat::identity()));
+    }
+
+    #[test]
+    fn mat3_inverse_undoes_the_matrix() {
+        let skewed =
+            Mat3::rotation_x(0.4) * Mat3::scale(Vec3::from(2.0, 0.5, 3.0)) * Mat3::rotation_z(-1.1);
+        let rotation = Mat3::rotation_y(0.7) * Mat3::rotation_x(-0.2);
+        let vector = Vec3::from(0.3, -0.4, 0.9);
+
+        let inverse = skewed.inverse().unwrap();
+        assert_close(inverse * (skewed * vector), vector);
+        assert_close(skewed * (inverse * vector), vector);
+
+        // Rotations are orthogonal, their transpose is their inverse
+        assert_close(rotation.transpose() * (rotation * vector), vector);
+        assert_close(
+            rotation.inverse().unwrap() * vector,
+            rotation.transpose() * vector,
+        );
+        assert_close(skewed.transpose().transpose() * vector, skewed * vector);
+
+        assert!(Mat3::scale(Vec3::from(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn mat4_transpose_swaps_rows_and_columns() {
+        let matrix = Mat4 {
+            rows: [
+                [1.0, 2.0, 3.0, 4.0],
+                [5.0, 6.0, 7.0, 8.0],
+                [9.0, 10.0, 11.0, 12.0],
+                [13.0, 14.0, 15.0, 16.0],
+            ],
+        };
+        let transposed = matrix.transpose();
+
+        for row in 0..4 {
+            for column in 0..4 {
+                assert_eq!(transposed.rows[row][column], matrix.rows[column][row]);
+            }
+        }
+
+        let rotation = Mat4::rotation(Quat::from_euler(Vec3::from(0.3, -1.2, 2.0)));
+        assert_identity(rotation * rotation.transpose());
+    }
+
+    #[test]
+    fn transforms_scale_then_rotate_then_translate() {
+        let scale = Vec3::from(2.0, 0.5, 3.0);
+        let rotation = Quat::from_euler(Vec3::from(0.3, -1.2, 2.0));
+        let translation = Vec3::from(1.0, -2.0, 5.0);
+        let point = Vec3::from(0.3, -0.4, 0.9);
+
+        assert_close(
+            Mat4::translation(translation).transform_point(&point),
+            point + translation,
+        );
+        assert_close(
+            Mat4::translation(translation).transform_vector(&point),
+            point,
+        );
+        assert_close(Mat4::scale(scale).transform_point(&point), point * scale);
+        assert_close(
+            Mat4::from_scale_rotation_translation(scale, rotation, translation)
+                .transform_point(&point),
+            rotation.rotate(&(point * scale)) + translation,
+        );
+    }
+
+    #[test]
+    fn perspective_maps_the_view_to_the_unit_cube() {
+        let projection = Mat4::perspective(FRAC_PI_2, 2.0, 1.0, 10.0);
+
+        assert_close(
+            projection.project_point(&Vec3::from(0.0, 0.0, 1.0)),
+            Vec3::zero(),
+        );
+        assert_close(
+            projection.project_point(&Vec3::from(0.0, 0.0, 10.0)),
+            Vec3::from(0.0, 0.0, 1.0),
+        );
+
+        // The corner of the view at a depth of 5, twice as wide as it is high
+        let depth = 10.0 / 9.0 * (1.0 - 1.0 / 5.0);
+        assert_close(
+            projection.project_point(&Vec3::from(-10.0, 5.0, 5.0)),
+            Vec3::from(-1.0, 1.0, depth),
+        );
+    }
+}
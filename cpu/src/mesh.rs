@@ -1,10 +1,28 @@
+use crate::bvh::*;
 use crate::object::*;
 //use crate::primitives::*;
 use crate::ray::*;
 use crate::math::*;
+use crate::sampling;
 
-pub trait MeshTrait {
+pub trait MeshTrait: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord>;
+
+    /// Box around the whole mesh, `None` if it is unbounded
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Surface area, infinite for unbounded meshes
+    fn area(&self) -> f32;
+
+    /// Point on the surface to light `from` with and its outward normal, `None` if the mesh is
+    /// unbounded. `pick` chooses between the parts of the surface. Uniformly distributed
+    /// unless the mesh can do better for `from`.
+    fn sample(&self, from: Vec3, u: [f32; 2], pick: f32) -> Option<(Vec3, Vec3)>;
+
+    /// Density of `sample` picking `point` per unit area
+    fn pdf(&self, _from: Vec3, _point: Vec3) -> f32 {
+        1.0 / self.area()
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -22,17 +40,79 @@ impl MeshTrait for Sphere {
         let discriminant = b.powi(2) - a * c;
 
         if discriminant > 0.0 {
-            let mut temp = (-b - discriminant.sqrt()) / a;
-
-            if temp < t_max && temp > t_min {
-                let point = ray.at(temp);
-                let normal = (point - self.center) / self.radius;
-                return Some(HitRecord { point, normal, t: temp, object_id });
+            // The far root is hit from inside, or when the near one is behind t_min
+            for temp in [(-b - discriminant.sqrt()) / a, (-b + discriminant.sqrt()) / a] {
+                if temp < t_max && temp > t_min {
+                    let point = ray.at(temp);
+                    let normal = (point - self.center) / self.radius;
+                    return Some(HitRecord::new(ray, point, normal, temp, object_id));
+                }
             }
         }
 
         None
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = Vec3::one() * self.radius.abs();
+        Some(Aabb {
+            min: self.center - radius,
+            max: self.center + radius,
+        })
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius.powi(2)
+    }
+
+    /// Seen from outside, only points in the cone of directions towards the sphere are
+    /// picked, the half facing away would be in its own shadow
+    fn sample(&self, from: Vec3, u: [f32; 2], _pick: f32) -> Option<(Vec3, Vec3)> {
+        let Some(cos_theta_max) = self.cos_theta_max(from) else {
+            let (normal, _) = sampling::uniform_sphere(u);
+            return Some((self.center + normal * self.radius, normal));
+        };
+
+        let axis = (self.center - from).normalized();
+        let (tangent, bitangent) = axis.orthonormal_basis();
+        let (local, _) = sampling::uniform_cone(u, cos_theta_max);
+        let direction = tangent * local.x + bitangent * local.y + axis * local.z;
+
+        // Nearest point along the direction, directions at the edge of the cone only graze the
+        // sphere so rounding can leave the discriminant a little below 0
+        let oc = from - self.center;
+        let b = oc.dot(&direction);
+        let c = oc.dot(&oc) - self.radius.powi(2);
+        let t = -b - (b * b - c).max(0.0).sqrt();
+        let point = from + direction * t;
+
+        Some((point, (point - self.center) / self.radius))
+    }
+
+    fn pdf(&self, from: Vec3, point: Vec3) -> f32 {
+        let Some(cos_theta_max) = self.cos_theta_max(from) else {
+            return 1.0 / self.area();
+        };
+
+        // Density per steradian of the cone, per unit area at the point it lands on
+        let offset = from - point;
+        let distance_squared = offset.dot(&offset);
+        let normal = (point - self.center) / self.radius.abs();
+        let cos_theta = offset.dot(&normal).abs() / distance_squared.sqrt();
+
+        sampling::uniform_cone_pdf(cos_theta_max) * cos_theta / distance_squared
+    }
+}
+
+impl Sphere {
+    /// Cosine of the angle between the centre and the edge of the sphere seen from `from`,
+    /// `None` from inside
+    fn cos_theta_max(&self, from: Vec3) -> Option<f32> {
+        let distance_squared = (self.center - from).dot(&(self.center - from));
+        let sin_theta_max_squared = self.radius.powi(2) / distance_squared;
+
+        (sin_theta_max_squared < 1.0).then(|| (1.0 - sin_theta_max_squared).sqrt())
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -59,70 +139,340 @@ impl MeshTrait for Plane {
 
         if t < t_max && t > t_min {
             let point = ray.at(t);
-            return Some(HitRecord { point, normal: self.normal, t, object_id });
+            return Some(HitRecord::new(ray, point, self.normal, t, object_id));
         }
 
         None
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    fn area(&self) -> f32 {
+        f32::INFINITY
+    }
+
+    fn sample(&self, _from: Vec3, _u: [f32; 2], _pick: f32) -> Option<(Vec3, Vec3)> {
+        None
+    }
+}
+
+/// Parallelogram spanned by `u` and `v` from `corner`, facing along `u` cross `v`
+#[derive(Clone, Copy)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+}
+
+impl MeshTrait for Quad {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
+        let normal = self.u.cross(&self.v);
+        let denominator = ray.direction.dot(&normal);
+
+        if denominator.abs() < 0.000001 {
+            return None;
+        }
+
+        let t = (self.corner - ray.origin).dot(&normal) / denominator;
+
+        if t >= t_max || t <= t_min {
+            return None;
+        }
+
+        // Coordinates of the hit along the edges, both within 0 to 1 inside the quad
+        let point = ray.at(t);
+        let offset = point - self.corner;
+        let length_squared = normal.dot(&normal);
+        let a = offset.cross(&self.v).dot(&normal) / length_squared;
+        let b = self.u.cross(&offset).dot(&normal) / length_squared;
+
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+
+        Some(HitRecord::new(ray, point, normal.normalized(), t, object_id))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points([
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ]))
+    }
+
+    fn area(&self) -> f32 {
+        self.u.cross(&self.v).length()
+    }
+
+    fn sample(&self, _from: Vec3, u: [f32; 2], _pick: f32) -> Option<(Vec3, Vec3)> {
+        Some((
+            self.corner + self.u * u[0] + self.v * u[1],
+            self.u.cross(&self.v).normalized(),
+        ))
+    }
 }
 
 #[derive(Clone)]
 pub struct Mesh{
     pub vertices: Vec<Vec3>,
+    /// Three vertex indices per triangle
     pub indices: Vec<usize>,
+    /// One per vertex, blended across each triangle for smooth shading. Empty uses the flat
+    /// triangle normals.
     pub normals: Vec<Vec3>,
+    bvh: Bvh,
+    /// Running total of the triangle areas, for picking triangles by area
+    areas: Vec<f32>,
 }
 
-impl MeshTrait for Mesh {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
-        let mut hit_record = None;
+impl Mesh {
+    /// Create a mesh and build the BVH over its triangles
+    pub fn new(vertices: Vec<Vec3>, indices: Vec<usize>, normals: Vec<Vec3>) -> Self {
+        let bvh = Bvh::new((0..indices.len() / 3).map(|triangle| {
+            Some(Aabb::from_points(
+                indices[triangle * 3..triangle * 3 + 3]
+                    .iter()
+                    .map(|&i| vertices[i]),
+            ))
+        }));
+
+        let areas = (0..indices.len() / 3)
+            .scan(0.0, |total, triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| vertices[indices[triangle * 3 + i]]);
+                *total += (b - a).cross(&(c - a)).length() * 0.5;
+                Some(*total)
+            })
+            .collect();
+
+        Self {
+            vertices,
+            indices,
+            normals,
+            bvh,
+            areas,
+        }
+    }
 
-        for i in (0..self.indices.len()).step_by(3) {
-            let i0 = self.indices[i];
-            let i1 = self.indices[i + 1];
-            let i2 = self.indices[i + 2];
+    /// Sphere made of `rings` bands of `segments` quads, split into triangles
+    pub fn uv_sphere(center: Vec3, radius: f32, rings: usize, segments: usize) -> Self {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
 
-            let v0 = self.vertices[i0];
-            let v1 = self.vertices[i1];
-            let v2 = self.vertices[i2];
+        for ring in 0..=rings {
+            let theta = ring as f32 / rings as f32 * std::f32::consts::PI;
 
-            let e1 = v1 - v0;
-            let e2 = v2 - v0;
-            let p = ray.direction.cross(&e2);
-            let det = e1.dot(&p);
+            for segment in 0..=segments {
+                let phi = segment as f32 / segments as f32 * std::f32::consts::TAU;
+                vertices.push(
+                    center
+                        + Vec3::from(
+                            theta.sin() * phi.cos(),
+                            theta.cos(),
+                            theta.sin() * phi.sin(),
+                        ) * radius,
+                );
+            }
+        }
 
-            if det > -0.000001 && det < 0.000001 {
-                continue;
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = ring * (segments + 1) + segment;
+                let b = a + segments + 1;
+                indices.extend([a, b, a + 1, a + 1, b, b + 1]);
             }
+        }
 
-            let inv_det = 1.0 / det;
-            let t = ray.origin - v0;
-            let u = t.dot(&p) * inv_det;
+        Self::new(vertices, indices, Vec::new())
+    }
 
-            if u < 0.0 || u > 1.0 {
-                continue;
-            }
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
 
-            let q = t.cross(&e1);
-            let v = ray.direction.dot(&q) * inv_det;
+    /// Test every triangle in order without the BVH, used to benchmark it
+    pub fn hit_linear(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
+        let mut hit_record = None;
+        let mut closest_so_far = t_max;
 
-            if v < 0.0 || u + v > 1.0 {
-                continue;
+        for triangle in 0..self.triangle_count() {
+            if let Some(temp_hit_record) = self.hit_triangle(triangle, ray, t_min, closest_so_far, object_id) {
+                closest_so_far = temp_hit_record.t;
+                hit_record = Some(temp_hit_record);
             }
+        }
+
+        hit_record
+    }
+
+    fn hit_triangle(&self, triangle: usize, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
+        let i0 = self.indices[triangle * 3];
+        let i1 = self.indices[triangle * 3 + 1];
+        let i2 = self.indices[triangle * 3 + 2];
 
-            let temp = e2.dot(&q) * inv_det;
+        let v0 = self.vertices[i0];
+        let v1 = self.vertices[i1];
+        let v2 = self.vertices[i2];
 
-            if temp < t_max && temp > t_min {
-                hit_record = Some(HitRecord {
-                    point: ray.at(temp),
-                    normal: (e1.cross(&e2)).normalized(),
-                    t: temp,
-                    object_id,
-                });
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+        let p = ray.direction.cross(&e2);
+        let det = e1.dot(&p);
+
+        if det > -0.000001 && det < 0.000001 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let t = ray.origin - v0;
+        let u = t.dot(&p) * inv_det;
+
+        if u < 0.0 || u > 1.0 {
+            return None;
+        }
+
+        let q = t.cross(&e1);
+        let v = ray.direction.dot(&q) * inv_det;
+
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let temp = e2.dot(&q) * inv_det;
+
+        if temp < t_max && temp > t_min {
+            let mut hit_record = HitRecord::new(ray, ray.at(temp), (e1.cross(&e2)).normalized(), temp, object_id);
+
+            // The side that was hit comes from the flat normal, the vertex normals only shade
+            if self.normals.len() == self.vertices.len() {
+                let normal = (self.normals[i0] * (1.0 - u - v) + self.normals[i1] * u + self.normals[i2] * v)
+                    .normalized();
+                hit_record.normal = if hit_record.front_face { normal } else { -normal };
             }
+
+            return Some(hit_record);
         }
 
-        hit_record
+        None
     }
-    
-}
\ No newline at end of file
+}
+
+impl MeshTrait for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
+        self.bvh.hit(ray, t_min, t_max, |triangle, t_max| {
+            self.hit_triangle(triangle, ray, t_min, t_max, object_id)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(self.indices.iter().map(|&i| self.vertices[i])))
+    }
+
+    fn area(&self) -> f32 {
+        self.areas.last().copied().unwrap_or(0.0)
+    }
+
+    fn sample(&self, _from: Vec3, u: [f32; 2], pick: f32) -> Option<(Vec3, Vec3)> {
+        let target = pick * self.area();
+        let triangle = self
+            .areas
+            .partition_point(|&total| total <= target)
+            .min(self.triangle_count().checked_sub(1)?);
+
+        let [a, b, c] = [0, 1, 2].map(|i| self.vertices[self.indices[triangle * 3 + i]]);
+        let (point, _) = sampling::uniform_triangle(u, a, b, c);
+
+        Some((point, (b - a).cross(&(c - a)).normalized()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use std::f32::consts::TAU;
+
+    #[test]
+    fn sphere_samples_the_cap_it_can_see() {
+        let sphere = Sphere {
+            center: Vec3::from(1.0, -2.0, 0.5),
+            radius: 2.0,
+        };
+        let from = sphere.center + Vec3::from(3.0, 4.0, 0.0);
+        let mut rng = Rng::new(5, 0);
+
+        // Averaging 1 / pdf over the samples measures the area they are spread over, the cap
+        // closer to `from` than the tangent points
+        let samples = 65536;
+        let mut area = 0.0;
+        for _ in 0..samples {
+            let (point, normal) = sphere.sample(from, rng.next_2d(), 0.0).unwrap();
+            assert!(((point - sphere.center).length() - sphere.radius).abs() < 1e-4);
+            assert!(
+                (from - point).dot(&normal) >= -1e-4,
+                "{:?} faces away",
+                point
+            );
+
+            area += 1.0 / sphere.pdf(from, point);
+        }
+
+        let expected = TAU * sphere.radius.powi(2) * (1.0 - sphere.radius / 5.0);
+        let area = area / samples as f32;
+        assert!(
+            (area - expected).abs() < 0.01 * expected,
+            "{} != {}",
+            area,
+            expected
+        );
+    }
+
+    #[test]
+    fn sphere_samples_evenly_from_inside() {
+        let sphere = Sphere {
+            center: Vec3::zero(),
+            radius: 2.0,
+        };
+        let from = Vec3::from(0.5, 0.0, 0.0);
+
+        let (point, _) = sphere.sample(from, [0.3, 0.6], 0.0).unwrap();
+        assert_eq!(sphere.pdf(from, point), 1.0 / sphere.area());
+    }
+
+    #[test]
+    fn vertex_normals_only_change_the_shading_normal() {
+        let tilted = Vec3::from(1.0, 0.0, -1.0).normalized();
+        let mesh = Mesh::new(
+            vec![
+                Vec3::from(0.0, 0.0, 0.0),
+                Vec3::from(1.0, 0.0, 0.0),
+                Vec3::from(0.0, 1.0, 0.0),
+            ],
+            vec![0, 1, 2],
+            vec![tilted; 3],
+        );
+
+        for (z, facing) in [(1.0, 1.0), (-1.0, -1.0)] {
+            let ray = Ray {
+                origin: Vec3::from(0.25, 0.25, z),
+                direction: Vec3::from(0.0, 0.0, -z),
+            };
+            let hit_record = mesh.hit(&ray, 0.0, f32::MAX, 0).unwrap();
+
+            assert_eq!(hit_record.front_face, facing > 0.0);
+            assert_eq!(hit_record.geometric_normal, Vec3::from(0.0, 0.0, facing));
+            assert!((hit_record.normal - tilted * facing).length() < 1e-6);
+
+            // Grazing the surface on the shading normal's side still leaves from the side
+            // the ray came from
+            let direction = Vec3::from(-facing, 0.0, 0.1 * facing).normalized();
+            assert!(direction.dot(&hit_record.normal) < 0.0);
+            let spawned = hit_record.spawn_ray(direction);
+            assert!(spawned.origin.z * facing > 0.0);
+            assert!(mesh.hit(&spawned, 0.0, f32::MAX, 0).is_none());
+        }
+    }
+}
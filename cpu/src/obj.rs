@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::material::*;
+use crate::math::Vec3;
+use crate::mesh::Mesh;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Material from a .mtl file, only the parts the renderer has a material for
+#[derive(Clone, Copy, Debug)]
+pub struct ObjMaterial {
+    /// `Kd`
+    pub diffuse: Vec3,
+    /// `Ke`, a material with any emission becomes a light
+    pub emission: Vec3,
+}
+
+impl ObjMaterial {
+    pub fn build(&self) -> Box<dyn Material> {
+        if self.emission != Vec3::zero() {
+            Box::new(PointLightMaterial {
+                color: self.emission,
+            })
+        } else {
+            Diffuse::boxed(self.diffuse)
+        }
+    }
+}
+
+/// Faces that share a group and a material
+pub struct ObjGroup {
+    /// Name given to `usemtl` and the line it was on, `None` before the first `usemtl`
+    pub material: Option<(String, usize)>,
+    pub mesh: Mesh,
+}
+
+pub struct Obj {
+    pub path: PathBuf,
+    pub groups: Vec<ObjGroup>,
+    /// Materials from every `mtllib`
+    pub materials: HashMap<String, ObjMaterial>,
+}
+
+/// Triangles of a group while the file is read, before the vertices are deduplicated
+struct GroupBuilder {
+    /// Only used to start a new group on `usemtl`, each group becomes its own object anyway
+    name: String,
+    material: Option<(String, usize)>,
+    /// Position, texture coordinate and normal index per corner
+    corners: Vec<(usize, Option<usize>, Option<usize>)>,
+}
+
+/// Read a Wavefront OBJ file and the material libraries it references. Polygons are
+/// triangulated as fans, so they should be convex.
+pub fn load(path: &Path) -> Result<Obj, ObjError> {
+    parse(&read(path)?, path)
+}
+
+/// Build an OBJ from its source, `path` is where errors say it is and where `mtllib` looks
+/// for material libraries
+pub fn parse(source: &str, path: &Path) -> Result<Obj, ObjError> {
+    let error = |line: usize, message: String| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+
+    let mut positions = Vec::new();
+    let mut texture_coordinates = 0;
+    let mut normals = Vec::new();
+    let mut materials = HashMap::new();
+    let mut groups = vec![GroupBuilder {
+        name: String::new(),
+        material: None,
+        corners: Vec::new(),
+    }];
+
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let arguments = words.collect::<Vec<&str>>();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&arguments).map_err(|e| error(number, e))?),
+            "vn" => normals.push(parse_vec3(&arguments).map_err(|e| error(number, e))?),
+            "vt" => {
+                if arguments.is_empty() || arguments.len() > 3 {
+                    return Err(error(
+                        number,
+                        format!("expected 1 to 3 numbers, got {}", arguments.len()),
+                    ));
+                }
+                for argument in &arguments {
+                    parse_f32(argument).map_err(|e| error(number, e))?;
+                }
+                texture_coordinates += 1;
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(error(
+                        number,
+                        format!("a face needs at least 3 vertices, got {}", arguments.len()),
+                    ));
+                }
+
+                let corners = arguments
+                    .iter()
+                    .map(|corner| {
+                        parse_corner(corner, positions.len(), texture_coordinates, normals.len())
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+                    .map_err(|e| error(number, e))?;
+
+                let group = groups.last_mut().unwrap();
+                for i in 1..corners.len() - 1 {
+                    group
+                        .corners
+                        .extend([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                let material = groups.last().unwrap().material.clone();
+                groups.push(GroupBuilder {
+                    name: arguments.join(" "),
+                    material,
+                    corners: Vec::new(),
+                });
+            }
+            "usemtl" => {
+                if arguments.is_empty() {
+                    return Err(error(number, "usemtl needs a material name".to_string()));
+                }
+                let name = groups.last().unwrap().name.clone();
+                groups.push(GroupBuilder {
+                    name,
+                    material: Some((arguments.join(" "), number)),
+                    corners: Vec::new(),
+                });
+            }
+            "mtllib" => {
+                if arguments.is_empty() {
+                    return Err(error(number, "mtllib needs a file name".to_string()));
+                }
+                let directory = path.parent().unwrap_or(Path::new(""));
+                for library in &arguments {
+                    materials.extend(load_mtl(&directory.join(library))?);
+                }
+            }
+            // Smoothing groups, lines, points and curves don't affect the triangles
+            _ => {}
+        }
+    }
+
+    let groups = groups
+        .into_iter()
+        .filter(|group| !group.corners.is_empty())
+        .map(|group| build_group(group, &positions, &normals))
+        .collect();
+
+    Ok(Obj {
+        path: path.to_path_buf(),
+        groups,
+        materials,
+    })
+}
+
+/// Read a .mtl material library
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    parse_mtl(&read(path)?, path)
+}
+
+/// Build a material library from its source, `path` is only used in error messages
+pub fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    let error = |line: usize, message: String| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, ObjMaterial)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let arguments = words.collect::<Vec<&str>>();
+
+        match keyword {
+            "newmtl" => {
+                if arguments.is_empty() {
+                    return Err(error(number, "newmtl needs a material name".to_string()));
+                }
+                materials.extend(current.take());
+                current = Some((
+                    arguments.join(" "),
+                    ObjMaterial {
+                        diffuse: Vec3::one() * 0.8,
+                        emission: Vec3::zero(),
+                    },
+                ));
+            }
+            "Kd" | "Ke" => {
+                let Some((_, material)) = current.as_mut() else {
+                    return Err(error(number, format!("{} before newmtl", keyword)));
+                };
+                let color = parse_vec3(&arguments).map_err(|e| error(number, e))?;
+                match keyword {
+                    "Kd" => material.diffuse = color,
+                    _ => material.emission = color,
+                }
+            }
+            // Everything else, specular, transparency, texture maps, has no material to map to
+            _ => {}
+        }
+    }
+
+    materials.extend(current);
+
+    Ok(materials)
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn parse_f32(s: &str) -> Result<f32, String> {
+    s.parse::<f32>()
+        .map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn parse_vec3(arguments: &[&str]) -> Result<Vec3, String> {
+    match arguments {
+        [x, y, z] => Ok(Vec3::from(parse_f32(x)?, parse_f32(y)?, parse_f32(z)?)),
+        // Positions may have a fourth, w, component which we don't use
+        [x, y, z, w] => {
+            parse_f32(w)?;
+            Ok(Vec3::from(parse_f32(x)?, parse_f32(y)?, parse_f32(z)?))
+        }
+        _ => Err(format!("expected 3 numbers, got {}", arguments.len())),
+    }
+}
+
+/// Parse `v`, `v/vt`, `v//vn` or `v/vt/vn` into zero based indices. Negative indices count
+/// back from the last element read so far.
+fn parse_corner(
+    corner: &str,
+    positions: usize,
+    texture_coordinates: usize,
+    normals: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = corner.split('/');
+    let position = parts.next().unwrap_or_default();
+    let texture_coordinate = parts.next().filter(|s| !s.is_empty());
+    let normal = parts.next().filter(|s| !s.is_empty());
+
+    if parts.next().is_some() {
+        return Err(format!("invalid face vertex `{}`", corner));
+    }
+
+    Ok((
+        resolve_index(position, positions, "vertex")?,
+        texture_coordinate
+            .map(|i| resolve_index(i, texture_coordinates, "texture coordinate"))
+            .transpose()?,
+        normal
+            .map(|i| resolve_index(i, normals, "normal"))
+            .transpose()?,
+    ))
+}
+
+fn resolve_index(index: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let value = index
+        .parse::<i64>()
+        .map_err(|_| format!("invalid {} index `{}`", kind, index))?;
+
+    let resolved = match value {
+        0 => None,
+        1.. => Some(value - 1),
+        _ => Some(count as i64 + value),
+    };
+
+    match resolved {
+        Some(i) if (0..count as i64).contains(&i) => Ok(i as usize),
+        _ => Err(format!(
+            "{} index {} is out of range, {} defined so far",
+            kind, value, count
+        )),
+    }
+}
+
+/// Give every distinct position and normal pair its own vertex, since `Mesh` uses one index
+/// per corner
+fn build_group(group: GroupBuilder, positions: &[Vec3], normals: &[Vec3]) -> ObjGroup {
+    let has_normals = group.corners.iter().all(|(_, _, n)| n.is_some());
+    let mut vertices = Vec::new();
+    let mut vertex_normals = Vec::new();
+    let mut indices = Vec::new();
+    let mut seen = HashMap::new();
+
+    for &(position, _, normal) in &group.corners {
+        let normal = normal.filter(|_| has_normals);
+        let index = *seen.entry((position, normal)).or_insert_with(|| {
+            vertices.push(positions[position]);
+            if let Some(normal) = normal {
+                vertex_normals.push(normals[normal]);
+            }
+            vertices.len() - 1
+        });
+        indices.push(index);
+    }
+
+    ObjGroup {
+        material: group.material,
+        mesh: Mesh::new(vertices, indices, vertex_normals),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_source(name: &str, source: &str) -> Result<Obj, ObjError> {
+        parse(source, Path::new(name))
+    }
+
+    #[test]
+    fn negative_indices_count_back() {
+        let obj = load_source(
+            "negative.obj",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 0 0 1\nf 1 -1 2\n",
+        )
+        .unwrap();
+        let mesh = &obj.groups[0].mesh;
+
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 3, 1]);
+        assert_eq!(mesh.vertices[3], Vec3::from(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn polygons_become_fans() {
+        let obj = load_source(
+            "fan.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\nf 1 2 3 4 5\n",
+        )
+        .unwrap();
+
+        assert_eq!(obj.groups[0].mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn usemtl_starts_a_group() {
+        let obj = load_source(
+            "usemtl.obj",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl red\nf 1 2 3\nf 3 2 1\nusemtl blue\nf 1 2 3\nusemtl red\nf 1 2 3\n",
+        )
+        .unwrap();
+        let groups = obj
+            .groups
+            .iter()
+            .map(|group| (group.material.clone(), group.mesh.triangle_count()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            groups,
+            vec![
+                (None, 1),
+                (Some(("red".to_string(), 5)), 2),
+                (Some(("blue".to_string(), 8)), 1),
+                (Some(("red".to_string(), 10)), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn normals_split_shared_positions() {
+        let obj = load_source(
+            "normals.obj",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvn 0 0 1\nvn 0 1 0\nf 1//1 2//1 3//1\nf 2//2 4//2 3//2\n",
+        )
+        .unwrap();
+        let mesh = &obj.groups[0].mesh;
+
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.normals.len(), mesh.vertices.len());
+        assert_eq!(mesh.normals[3], Vec3::from(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        let cases = [
+            ("range.obj", "v 0 0 0\n\nf 1 2 3\n", 3, "out of range"),
+            ("number.obj", "v 0 0 0\nv 0 x 0\n", 2, "invalid number"),
+            ("face.obj", "v 0 0 0\n# comment\nf 1 1\n", 3, "at least 3"),
+            ("zero.obj", "v 0 0 0\nf 0 1 1\n", 2, "out of range"),
+        ];
+
+        for (name, source, expected_line, expected_message) in cases {
+            match load_source(name, source) {
+                Err(ObjError::Parse { line, message, .. }) => {
+                    assert_eq!(line, expected_line, "{}", name);
+                    assert!(message.contains(expected_message), "{}: {}", name, message);
+                }
+                _ => panic!("{} should fail to parse", name),
+            }
+        }
+    }
+
+    #[test]
+    fn mtl_materials_keep_their_colors() {
+        let materials = parse_mtl(
+            "newmtl red\nKd 1 0 0\nNs 10\n\nnewmtl lamp\nKe 4 4 4\n",
+            Path::new("test.mtl"),
+        )
+        .unwrap();
+
+        assert_eq!(materials["red"].diffuse, Vec3::from(1.0, 0.0, 0.0));
+        assert_eq!(materials["red"].emission, Vec3::zero());
+        assert_eq!(materials["lamp"].diffuse, Vec3::one() * 0.8);
+        assert_eq!(materials["lamp"].emission, Vec3::one() * 4.0);
+
+        match parse_mtl("# no material yet\nKd 1 1 1\n", Path::new("test.mtl")) {
+            Err(ObjError::Parse { line, message, .. }) => {
+                assert_eq!((line, message.as_str()), (2, "Kd before newmtl"));
+            }
+            _ => panic!("Kd without a material should fail"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use crate::math::Vec3;
+use crate::bvh::Aabb;
+use crate::math::{Mat4, Quat, Vec3};
 use crate::ray::*;
 use crate::material::*;
 use crate::mesh::*;
@@ -6,19 +7,12 @@ use crate::mesh::*;
 #[derive(Clone, Copy)]
 pub struct Transform {
     pub position: Vec3,
+    /// Euler angles in degrees, applied around x, then y, then z
     pub rotation: Vec3,
     pub scale: Vec3,
 }
 
 impl Transform {
-    pub fn zero() -> Self {
-        Self {
-            position: Vec3::zero(),
-            rotation: Vec3::zero(),
-            scale: Vec3::one(),
-        }
-    }
-
     pub fn from(position: Vec3, rotation: Vec3, scale: Vec3) -> Self {
         Self {
             position,
@@ -34,53 +28,141 @@ impl Transform {
             scale: Vec3::one(),
         }
     }
+
+    /// Object to world matrix, scale first, then rotation, then translation
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation(), self.position)
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_euler(Vec3::from(
+            self.rotation.x.to_radians(),
+            self.rotation.y.to_radians(),
+            self.rotation.z.to_radians(),
+        ))
+    }
 }
 
 pub struct Object {
-    pub transform: Transform,
     pub material: Box<dyn Material>,
     mesh: Box<dyn MeshTrait>,
     pub id: usize,
+    to_world: Mat4,
+    to_object: Mat4,
+    /// How much the transform scales volumes, for converting densities to world space
+    determinant: f32,
 }
 
 impl Object {
+    /// Panics if the transform can't be inverted, which happens when a scale component is 0
+    pub fn new(transform: Transform, mesh: Box<dyn MeshTrait>, material: Box<dyn Material>, id: usize) -> Self {
+        let to_world = transform.matrix();
+        let to_object = to_world.inverse().expect("object transform must be invertible");
+
+        Self {
+            material,
+            mesh,
+            id,
+            to_world,
+            to_object,
+            determinant: to_world.to_mat3().determinant().abs(),
+        }
+    }
+
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.mesh.hit(ray, t_min, t_max, self.id)
+        // The direction isn't normalized, so t is the same in both spaces
+        let object_ray = Ray {
+            origin: self.to_object.transform_point(&ray.origin),
+            direction: self.to_object.transform_vector(&ray.direction),
+        };
+
+        let hit_record = self.mesh.hit(&object_ray, t_min, t_max, self.id)?;
+
+        Some(HitRecord {
+            point: ray.at(hit_record.t),
+            normal: self.to_object.transform_normal(&hit_record.normal).normalized(),
+            geometric_normal: self
+                .to_object
+                .transform_normal(&hit_record.geometric_normal)
+                .normalized(),
+            ..hit_record
+        })
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        let local = self.mesh.bounding_box()?;
+
+        Some(Aabb::from_points((0..8).map(|corner| {
+            self.to_world.transform_point(&Vec3::from(
+                if corner & 1 == 0 { local.min.x } else { local.max.x },
+                if corner & 2 == 0 { local.min.y } else { local.max.y },
+                if corner & 4 == 0 { local.min.z } else { local.max.z },
+            ))
+        })))
+    }
+
+    /// Point on the surface for lighting `from`, with its outward normal and the density of
+    /// picking it per unit area, `None` if the surface is unbounded
+    pub fn sample(&self, from: Vec3, u: [f32; 2], pick: f32) -> Option<(Vec3, Vec3, f32)> {
+        let (point, normal) = self.mesh.sample(self.to_object.transform_point(&from), u, pick)?;
+        let point = self.to_world.transform_point(&point);
+        let normal = self.to_object.transform_normal(&normal).normalized();
+
+        Some((point, normal, self.pdf(from, point, normal)))
+    }
+
+    /// Density of `sample` from `from` per unit area at `point` with the world space `normal`,
+    /// 0 for unbounded surfaces
+    pub fn pdf(&self, from: Vec3, point: Vec3, normal: Vec3) -> f32 {
+        let pdf = self.mesh.pdf(self.to_object.transform_point(&from), self.to_object.transform_point(&point));
+
+        // A patch of the mesh grows by det(M) / |M^T n| in world space
+        pdf * self.to_world.transform_normal(&normal).length() / self.determinant
+    }
+
+    /// Whether the surface gives off light and can be sampled
+    pub fn is_emitter(&self) -> bool {
+        let area = self.mesh.area();
+        self.material.is_emissive() && area > 0.0 && area.is_finite()
     }
 
     pub fn sphere(center: Vec3, radius: f32, material: Box<dyn Material>, id: usize) -> Self {
-        Self {
-            transform: Transform::from_position(center),
+        Self::new(
+            Transform::from_position(center),
+            Box::new(Sphere { center: Vec3::zero(), radius }),
             material,
-            mesh: Box::new(Sphere { center, radius }),
             id,
-        }
+        )
     }
 
     pub fn plane(point: Vec3, normal: Vec3, material: Box<dyn Material>, id: usize) -> Self {
-        Self {
-            transform: Transform::from_position(point),
+        Self::new(
+            Transform::from_position(point),
+            Box::new(Plane::new(Vec3::zero(), normal)),
             material,
-            mesh: Box::new(Plane::new(point, normal)),
             id,
-        }
+        )
     }
 
-    pub fn from_mesh(position: Vec3, mesh: Mesh, material: Box<dyn Material>, id: usize) -> Self {
-        Self {
-            transform: Transform::from_position(position),
+    pub fn quad(corner: Vec3, u: Vec3, v: Vec3, material: Box<dyn Material>, id: usize) -> Self {
+        Self::new(
+            Transform::from_position(corner),
+            Box::new(Quad { corner: Vec3::zero(), u, v }),
             material,
-            mesh: Box::new(mesh),
             id,
-        }
+        )
     }
 
-    pub fn point_light(position: Vec3, radius: f32, range: f32, color: Vec3, id: usize) -> Self {
-        Self {
-            transform: Transform::from_position(position),
-            material: Box::new(PointLightMaterial { color }),
-            mesh: Box::new(Sphere { center: position, radius }),
+    pub fn from_mesh(transform: Transform, mesh: Mesh, material: Box<dyn Material>, id: usize) -> Self {
+        Self::new(transform, Box::new(mesh), material, id)
+    }
+
+    pub fn point_light(position: Vec3, radius: f32, color: Vec3, id: usize) -> Self {
+        Self::new(
+            Transform::from_position(position),
+            Box::new(Sphere { center: Vec3::zero(), radius }),
+            Box::new(PointLightMaterial { color }),
             id,
-        }
+        )
     }
 }
@@ -0,0 +1,407 @@
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::material::Color;
+use crate::math::Vec3;
+
+/// What to do when the output file already exists
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Overwrite {
+    /// Fail without touching the existing file
+    #[default]
+    Never,
+    /// Replace the existing file
+    Always,
+    /// Write to the first free name, image.ppm, image1.ppm, image2.ppm, ...
+    Increment,
+}
+
+/// Image file format, picked from the file extension unless given explicitly
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// 8-bit RGB PNG
+    Png,
+    /// Binary PPM (P6)
+    Ppm,
+    /// ASCII PPM (P3)
+    PpmAscii,
+    /// Radiance RGBE, keeps values above 1.0
+    Hdr,
+    /// Portable Float Map, 32-bit float per channel
+    Pfm,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "ppm" => Some(Self::Ppm),
+            "hdr" => Some(Self::Hdr),
+            "pfm" => Some(Self::Pfm),
+            _ => None,
+        }
+    }
+
+    /// Encode an image stored as rows of `width` pixels
+    pub fn encode(self, width: u32, height: u32, image: &[Vec3]) -> Result<Vec<u8>, OutputError> {
+        match self {
+            Self::Png => png(width, height, image),
+            Self::Ppm => Ok(ppm_binary(width, height, image)),
+            Self::PpmAscii => Ok(ppm(width, height, image)),
+            Self::Hdr => Ok(hdr(width, height, image)),
+            Self::Pfm => Ok(pfm(width, height, image)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    Exists(PathBuf),
+    UnknownFormat(PathBuf),
+    Png(png::EncodingError),
+    Io { path: PathBuf, error: io::Error },
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exists(path) => write!(
+                f,
+                "{} already exists, use --overwrite always or --overwrite increment",
+                path.display()
+            ),
+            Self::UnknownFormat(path) => write!(
+                f,
+                "can't tell the image format of {}, use a .png, .ppm, .hdr or .pfm extension or --format",
+                path.display()
+            ),
+            Self::Png(error) => write!(f, "png encoding failed: {}", error),
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+/// Fail early, before rendering, if the image could not be written with this policy.
+/// Returns the format to write, `format` or the one matching the extension of `path`.
+pub fn check(
+    path: &Path,
+    format: Option<Format>,
+    overwrite: Overwrite,
+) -> Result<Format, OutputError> {
+    let format = format
+        .or_else(|| Format::from_path(path))
+        .ok_or_else(|| OutputError::UnknownFormat(path.to_path_buf()))?;
+
+    if overwrite == Overwrite::Never && path.exists() {
+        return Err(OutputError::Exists(path.to_path_buf()));
+    }
+
+    Ok(format)
+}
+
+/// Write `data` to `path`, creating missing directories. Returns the path that was written,
+/// which differs from `path` with `Overwrite::Increment`.
+pub fn write(path: &Path, overwrite: Overwrite, data: &[u8]) -> Result<PathBuf, OutputError> {
+    let io_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |error| OutputError::Io { path, error }
+    };
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+
+    let path = match overwrite {
+        Overwrite::Increment => free_path(path),
+        _ => path.to_path_buf(),
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(overwrite != Overwrite::Always)
+        .open(&path)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::AlreadyExists => OutputError::Exists(path.clone()),
+            _ => OutputError::Io {
+                path: path.clone(),
+                error,
+            },
+        })?;
+
+    file.write_all(data).map_err(io_error(&path))?;
+
+    Ok(path)
+}
+
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| e.to_string_lossy());
+    let mut current = 0;
+    let mut current_path = path.to_path_buf();
+
+    while current_path.exists() {
+        current += 1;
+        current_path = path.with_file_name(match &extension {
+            Some(extension) => format!("{}{}.{}", stem, current, extension),
+            None => format!("{}{}", stem, current),
+        });
+    }
+
+    current_path
+}
+
+fn rgb8(image: &[Vec3]) -> Vec<u8> {
+    image
+        .iter()
+        .flat_map(|x| {
+            let color = Color::from(*x);
+            [color.r, color.g, color.b]
+        })
+        .collect()
+}
+
+fn ppm(width: u32, height: u32, image: &[Vec3]) -> Vec<u8> {
+    let mut ppm = format!("P3\n{} {}\n255\n", width, height);
+
+    ppm += image
+        .iter()
+        .map(|x| Color::from(*x).to_string())
+        .collect::<Vec<String>>()
+        .chunks(width as usize)
+        .map(|x| x.join(" "))
+        .collect::<Vec<String>>()
+        .as_slice()
+        .join("\n")
+        .as_str();
+
+    ppm.into_bytes()
+}
+
+fn ppm_binary(width: u32, height: u32, image: &[Vec3]) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    ppm.extend(rgb8(image));
+    ppm
+}
+
+fn png(width: u32, height: u32, image: &[Vec3]) -> Result<Vec<u8>, OutputError> {
+    let mut data = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut data, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(OutputError::Png)?;
+    writer
+        .write_image_data(&rgb8(image))
+        .map_err(OutputError::Png)?;
+    writer.finish().map_err(OutputError::Png)?;
+
+    Ok(data)
+}
+
+/// Radiance RGBE with uncompressed scanlines
+fn hdr(width: u32, height: u32, image: &[Vec3]) -> Vec<u8> {
+    let mut hdr = format!(
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )
+    .into_bytes();
+    hdr.extend(image.iter().flat_map(|x| rgbe(*x)));
+    hdr
+}
+
+/// Shared exponent encoding, the mantissas are the channels scaled so the largest is in
+/// 128..256
+fn rgbe(color: Vec3) -> [u8; 4] {
+    let max = color.x.max(color.y).max(color.z);
+
+    if max.is_nan() || max <= 0.0 {
+        return [0, 0, 0, 0];
+    }
+
+    // The largest channel is in 2^(exponent - 1)..2^exponent. Capped at the largest exponent
+    // the byte holds, which also keeps infinity from overflowing.
+    let exponent = (max.log2().floor() as i32).min(126) + 1;
+
+    // An exponent byte of 0 means black, anything darker than the smallest exponent is black
+    if exponent < -127 {
+        return [0, 0, 0, 0];
+    }
+
+    // Denormal and tiny values need more than an f32 to scale up
+    let scale = 2f64.powi(8 - exponent);
+    let mantissa = |channel: f32| (f64::from(channel.max(0.0)) * scale) as u8;
+
+    [
+        mantissa(color.x),
+        mantissa(color.y),
+        mantissa(color.z),
+        (exponent + 128) as u8,
+    ]
+}
+
+/// Little endian Portable Float Map, which stores the bottom row first
+fn pfm(width: u32, height: u32, image: &[Vec3]) -> Vec<u8> {
+    let mut pfm = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+
+    for row in image.chunks(width as usize).rev() {
+        for color in row {
+            pfm.extend(color.x.to_le_bytes());
+            pfm.extend(color.y.to_le_bytes());
+            pfm.extend(color.z.to_le_bytes());
+        }
+    }
+
+    pfm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory of its own for one test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("cpu-output-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn format_comes_from_the_extension() {
+        assert_eq!(
+            check(Path::new("image.png"), None, Overwrite::Never).unwrap(),
+            Format::Png
+        );
+        assert_eq!(
+            check(Path::new("a/b.PFM"), None, Overwrite::Never).unwrap(),
+            Format::Pfm
+        );
+        assert_eq!(
+            check(Path::new("image.jpg"), Some(Format::Hdr), Overwrite::Never).unwrap(),
+            Format::Hdr
+        );
+
+        for path in ["image.jpg", "image", "image.png.bak"] {
+            match check(Path::new(path), None, Overwrite::Always) {
+                Err(OutputError::UnknownFormat(unknown)) => assert_eq!(unknown, Path::new(path)),
+                other => panic!("{}: {:?}", path, other),
+            }
+        }
+    }
+
+    #[test]
+    fn never_keeps_the_existing_file() {
+        let directory = TempDir::new("never");
+        let path = directory.0.join("image.ppm");
+
+        assert_eq!(write(&path, Overwrite::Never, b"first").unwrap(), path);
+        assert!(matches!(
+            check(&path, None, Overwrite::Never),
+            Err(OutputError::Exists(_))
+        ));
+        assert!(matches!(
+            write(&path, Overwrite::Never, b"second"),
+            Err(OutputError::Exists(_))
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+    }
+
+    #[test]
+    fn always_replaces_the_existing_file() {
+        let directory = TempDir::new("always");
+        let path = directory.0.join("image.ppm");
+
+        write(&path, Overwrite::Always, b"first longer").unwrap();
+        assert_eq!(check(&path, None, Overwrite::Always).unwrap(), Format::Ppm);
+        assert_eq!(write(&path, Overwrite::Always, b"second").unwrap(), path);
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+    }
+
+    #[test]
+    fn increment_writes_to_the_next_free_name() {
+        let directory = TempDir::new("increment");
+        let path = directory.0.join("image.ppm");
+
+        assert_eq!(write(&path, Overwrite::Increment, b"0").unwrap(), path);
+        assert_eq!(
+            check(&path, None, Overwrite::Increment).unwrap(),
+            Format::Ppm
+        );
+        assert_eq!(
+            write(&path, Overwrite::Increment, b"1").unwrap(),
+            directory.0.join("image1.ppm")
+        );
+        assert_eq!(
+            write(&path, Overwrite::Increment, b"2").unwrap(),
+            directory.0.join("image2.ppm")
+        );
+        assert_eq!(fs::read(&path).unwrap(), b"0");
+        assert_eq!(fs::read(directory.0.join("image2.ppm")).unwrap(), b"2");
+
+        let bare = directory.0.join("image");
+        write(&bare, Overwrite::Increment, b"0").unwrap();
+        assert_eq!(
+            write(&bare, Overwrite::Increment, b"1").unwrap(),
+            directory.0.join("image1")
+        );
+    }
+
+    #[test]
+    fn missing_directories_are_created() {
+        let directory = TempDir::new("directories");
+        let path = directory.0.join("renders").join("today").join("image.png");
+
+        assert_eq!(write(&path, Overwrite::Never, b"png").unwrap(), path);
+        assert_eq!(fs::read(&path).unwrap(), b"png");
+    }
+
+    #[test]
+    fn pfm_reads_back_exactly_from_the_bottom_row() {
+        let image = [
+            Vec3::from(0.5, 1e-40, 3e38),
+            Vec3::from(-1.0, 0.0, 12.5),
+            Vec3::from(f32::INFINITY, 2.0, 0.25),
+            Vec3::from(1.0, 2.0, 3.0),
+            Vec3::from(7.0, 8.0, 9.0),
+            Vec3::from(1e-3, 0.1, 100.0),
+        ];
+        let data = Format::Pfm.encode(3, 2, &image).unwrap();
+
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+
+        let values = data[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<f32>>();
+        let pixels = values
+            .chunks(3)
+            .map(|rgb| Vec3::from(rgb[0], rgb[1], rgb[2]))
+            .collect::<Vec<Vec3>>();
+
+        assert_eq!(pixels[..3], image[3..]);
+        assert_eq!(pixels[3..], image[..3]);
+    }
+}
@@ -0,0 +1,44 @@
+/// PCG32 (XSH RR), the same generator as the shader's `rand_pcg` but with 64 bits of state.
+/// Every pixel gets its own stream, so the image is the same no matter which thread renders
+/// it.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    /// Generator for `stream` out of the 2^63 independent sequences of `seed`
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.state = state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        xorshifted.rotate_right((state >> 59) as u32)
+    }
+
+    /// Random number between 0.0 and 1.0, never 1.0
+    pub fn next_f32(&mut self) -> f32 {
+        // The top 24 bits are all an f32 can hold without rounding up to 1.0
+        (self.next_u32() >> 8) as f32 * (1.0 / (1 << 24) as f32)
+    }
+
+    pub fn next_2d(&mut self) -> [f32; 2] {
+        [self.next_f32(), self.next_f32()]
+    }
+}
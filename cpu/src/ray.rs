@@ -1,5 +1,8 @@
 use crate::{math::*, object::Object};
 
+/// How far rays leaving a surface start off it
+const SPAWN_OFFSET: f32 = 1e-4;
+
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
@@ -13,11 +16,66 @@ impl Ray {
     pub fn reflect(&self, normal: Vec3) -> Vec3 {
         self.direction - normal * self.direction.dot(&normal) * 2.0
     }
+
+    /// Bend the direction through a surface with Snell's law, `eta_ratio` is the index of
+    /// refraction on this side over the one on the other side. `normal` faces against the ray.
+    /// `None` on total internal reflection.
+    pub fn refract(&self, normal: Vec3, eta_ratio: f32) -> Option<Vec3> {
+        let direction = self.direction.normalized();
+        let cos_theta = (-direction.dot(&normal)).min(1.0);
+        let perpendicular = (direction + normal * cos_theta) * eta_ratio;
+        let parallel_squared = 1.0 - perpendicular.dot(&perpendicular);
+
+        if parallel_squared < 0.0 {
+            return None;
+        }
+
+        Some(perpendicular - normal * parallel_squared.sqrt())
+    }
 }
 
 pub struct HitRecord {
     pub point: Vec3,
+    /// Shading normal, interpolated from vertex normals on smooth meshes. Always faces against
+    /// the ray.
     pub normal: Vec3,
+    /// Normal of the actual surface, for everything but shading: which side was hit, light
+    /// densities and where rays leave from. Always faces against the ray.
+    pub geometric_normal: Vec3,
     pub t: f32,
     pub object_id: usize,
+    /// Whether the ray hit the outside of the surface, the side the outward normal points to
+    pub front_face: bool,
+}
+
+impl HitRecord {
+    /// Flips `outward_normal` to face against `ray` and remembers which side was hit
+    pub fn new(ray: &Ray, point: Vec3, outward_normal: Vec3, t: f32, object_id: usize) -> Self {
+        let front_face = ray.direction.dot(&outward_normal) < 0.0;
+        let normal = if front_face { outward_normal } else { -outward_normal };
+
+        Self {
+            point,
+            normal,
+            geometric_normal: normal,
+            t,
+            object_id,
+            front_face,
+        }
+    }
+
+    /// Ray leaving along `direction`, nudged off the surface on the side it leaves from so it
+    /// can't hit the same surface right away
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        let offset = if direction.dot(&self.geometric_normal) < 0.0 {
+            -self.geometric_normal
+        } else {
+            self.geometric_normal
+        };
+
+        Ray {
+            origin: self.point + offset * SPAWN_OFFSET,
+            direction,
+        }
+    }
 }
\ No newline at end of file
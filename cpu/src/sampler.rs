@@ -0,0 +1,446 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::random::Rng;
+
+/// Source of the random numbers for one pixel sample at a time. Every call takes the next
+/// dimension, the camera uses the first ones and each bounce the following ones, so a
+/// low-discrepancy sampler spreads each decision evenly over the samples of a pixel.
+pub trait Sampler {
+    /// Start sample `index` of `pixel`, from the first dimension
+    fn start(&mut self, pixel: u64, index: u32);
+
+    /// Number between 0.0 and 1.0, never 1.0
+    fn next_1d(&mut self) -> f32;
+
+    fn next_2d(&mut self) -> [f32; 2];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    /// Independent uniform numbers
+    Independent,
+    /// Jittered strata, shuffled separately for every dimension
+    Stratified,
+    /// Halton sequence with a random shift per pixel
+    Halton,
+    /// Owen scrambled Sobol sequence
+    Sobol,
+}
+
+impl SamplerKind {
+    /// Sampler for `samples` samples per pixel, the same `seed` gives the same numbers
+    pub fn create(self, seed: u64, samples: u32) -> Box<dyn Sampler> {
+        match self {
+            Self::Independent => Box::new(Independent {
+                seed,
+                rng: Rng::new(seed, 0),
+            }),
+            Self::Stratified => Box::new(Stratified {
+                seed,
+                samples,
+                pixel: 0,
+                index: 0,
+                dimension: 0,
+                rng: Rng::new(seed, 0),
+            }),
+            Self::Halton => Box::new(Halton {
+                seed,
+                pixel: 0,
+                index: 0,
+                dimension: 0,
+            }),
+            Self::Sobol => Box::new(Sobol {
+                seed,
+                pixel: 0,
+                index: 0,
+                dimension: 0,
+            }),
+        }
+    }
+}
+
+pub struct Independent {
+    seed: u64,
+    rng: Rng,
+}
+
+impl Sampler for Independent {
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.rng = Rng::new(hash(self.seed, index as u64, 0), pixel);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        self.rng.next_2d()
+    }
+}
+
+/// One jittered sample per stratum, 1D samples use `samples` strata and 2D samples a square
+/// grid with at least that many cells
+pub struct Stratified {
+    seed: u64,
+    samples: u32,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+    /// Jitter inside the strata
+    rng: Rng,
+}
+
+impl Stratified {
+    /// Stratum of the current sample for the next dimension, out of `count`
+    fn stratum(&mut self, count: u32) -> u32 {
+        let key = hash(self.seed, self.pixel, self.dimension) as u32;
+        self.dimension += 1;
+        permute(self.index % count, count, key)
+    }
+}
+
+impl Sampler for Stratified {
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Rng::new(hash(self.seed, index as u64, 1), pixel);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let stratum = self.stratum(self.samples);
+        to_unit((stratum as f32 + self.rng.next_f32()) / self.samples as f32)
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        let side = (self.samples as f32).sqrt().ceil() as u32;
+        let stratum = self.stratum(side * side);
+        let [x, y] = self.rng.next_2d();
+
+        [
+            to_unit(((stratum % side) as f32 + x) / side as f32),
+            to_unit(((stratum / side) as f32 + y) / side as f32),
+        ]
+    }
+}
+
+/// First primes, the bases of the Halton dimensions
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+pub struct Halton {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl Sampler for Halton {
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let key = hash(self.seed, self.pixel, self.dimension);
+        let shift = (key >> 40) as f32 / (1u64 << 24) as f32;
+
+        // Past the last base the sequence is too correlated to use, fall back to random
+        let value = match PRIMES.get(self.dimension as usize) {
+            Some(&base) => radical_inverse(base, self.index),
+            None => Rng::new(key, self.index as u64).next_f32(),
+        };
+        self.dimension += 1;
+
+        // Shifting every pixel differently turns the shared sequence's aliasing into noise
+        to_unit((value + shift).fract())
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        [self.next_1d(), self.next_1d()]
+    }
+}
+
+/// Burley, "Practical Hash-based Owen Scrambling". Every 2D sample uses the first two Sobol
+/// dimensions, shuffled and scrambled differently per dimension and pixel.
+pub struct Sobol {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl Sobol {
+    fn next_key(&mut self) -> u32 {
+        let key = hash(self.seed, self.pixel, self.dimension) as u32;
+        self.dimension += 1;
+        key
+    }
+}
+
+impl Sampler for Sobol {
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let key = self.next_key();
+        let index = nested_uniform_scramble(self.index, key);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_u32(key, 0));
+        unit_from_bits(x)
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        let key = self.next_key();
+        let index = nested_uniform_scramble(self.index, key);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_u32(key, 0));
+        let y = nested_uniform_scramble(sobol_second_dimension(index), hash_u32(key, 1));
+        [unit_from_bits(x), unit_from_bits(y)]
+    }
+}
+
+/// Second dimension of the Sobol sequence, from the primitive polynomial x + 1
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    result
+}
+
+/// Owen scrambling of the bits of `x`, every bit is flipped depending on the bits above it
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Hash where every bit only depends on the bits below it
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Digits of `index` in `base` mirrored around the decimal point
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut result = 0.0;
+    let mut scale = inverse_base;
+
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+
+    result as f32
+}
+
+/// Element `index` of a random permutation of `0..count` chosen by `key`, Kensler,
+/// "Correlated Multi-Jittered Sampling"
+fn permute(mut index: u32, count: u32, key: u32) -> u32 {
+    let mut mask = count.wrapping_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    // Permute the power of two range until the result lands inside `count`
+    loop {
+        index ^= key;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= key >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= key >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= key >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | key >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < count {
+            break;
+        }
+    }
+
+    (index.wrapping_add(key)) % count
+}
+
+/// Mix three numbers into one, the SplitMix64 finalizer
+fn hash(seed: u64, a: u64, b: u64) -> u64 {
+    let mut x =
+        seed ^ a.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ b.wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn hash_u32(key: u32, value: u32) -> u32 {
+    hash(key as u64, value as u64, 0) as u32
+}
+
+fn unit_from_bits(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Rounding can land exactly on 1.0, which is outside the range samples are promised to be in
+fn to_unit(x: f32) -> f32 {
+    x.min(1.0 - f32::EPSILON / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every sample of `pixel` for `dimensions` calls to `next_1d` followed by one `next_2d`
+    fn draw(
+        kind: SamplerKind,
+        samples: u32,
+        pixel: u64,
+        dimensions: usize,
+    ) -> Vec<(Vec<f32>, [f32; 2])> {
+        let mut sampler = kind.create(3, samples);
+
+        (0..samples)
+            .map(|index| {
+                sampler.start(pixel, index);
+                let values = (0..dimensions).map(|_| sampler.next_1d()).collect();
+                (values, sampler.next_2d())
+            })
+            .collect()
+    }
+
+    fn assert_one_per_cell(cells: impl Iterator<Item = usize>, count: usize) {
+        let mut seen = vec![0; count];
+        for cell in cells {
+            seen[cell] += 1;
+        }
+        assert!(seen.iter().all(|&n| n == 1), "{:?}", seen);
+    }
+
+    #[test]
+    fn stratified_gives_one_sample_per_stratum() {
+        for pixel in [0, 7, 12345] {
+            let samples = draw(SamplerKind::Stratified, 16, pixel, 3);
+
+            for dimension in 0..3 {
+                assert_one_per_cell(
+                    samples
+                        .iter()
+                        .map(|(values, _)| (values[dimension] * 16.0) as usize),
+                    16,
+                );
+            }
+            assert_one_per_cell(
+                samples
+                    .iter()
+                    .map(|(_, [x, y])| (x * 4.0) as usize + 4 * (y * 4.0) as usize),
+                16,
+            );
+        }
+
+        // Counts that aren't square leave some cells of the 2D grid empty, but never fill one
+        // twice
+        let samples = draw(SamplerKind::Stratified, 10, 3, 1);
+        assert_one_per_cell(
+            samples
+                .iter()
+                .map(|(values, _)| (values[0] * 10.0) as usize),
+            10,
+        );
+        let mut cells = samples
+            .iter()
+            .map(|(_, [x, y])| (x * 4.0) as usize + 4 * (y * 4.0) as usize)
+            .collect::<Vec<_>>();
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), 10);
+    }
+
+    #[test]
+    fn radical_inverse_mirrors_the_digits() {
+        let base_2 = (0..8).map(|i| radical_inverse(2, i)).collect::<Vec<_>>();
+        assert_eq!(base_2, [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875]);
+
+        let base_3 = (0..6)
+            .map(|i| radical_inverse(3, i) * 9.0)
+            .collect::<Vec<_>>();
+        for (value, expected) in base_3.iter().zip([0.0, 3.0, 6.0, 1.0, 4.0, 7.0]) {
+            assert!((value - expected).abs() < 1e-5, "{:?}", base_3);
+        }
+
+        // 1234 is 10011010010 in binary
+        assert_eq!(radical_inverse(2, 1234), 0b01001011001 as f32 / 2048.0);
+    }
+
+    #[test]
+    fn halton_shifts_each_pixel_by_a_constant() {
+        for pixel in [0, 42] {
+            let samples = draw(SamplerKind::Halton, 27, pixel, 2);
+
+            for (dimension, base) in [(0, 2), (1, 3)] {
+                let shift = samples[0].0[dimension];
+
+                for (index, (values, _)) in samples.iter().enumerate() {
+                    let unshifted = (values[dimension] - shift).rem_euclid(1.0);
+                    let expected = radical_inverse(base, index as u32);
+                    let difference = (unshifted - expected).abs();
+                    assert!(difference.min(1.0 - difference) < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scrambled_sobol_stays_in_range_and_stratified() {
+        for pixel in [0, 99, 1 << 40] {
+            let samples = draw(SamplerKind::Sobol, 256, pixel, 4);
+
+            for (values, [x, y]) in &samples {
+                assert!(values.iter().chain([x, y]).all(|v| (0.0..1.0).contains(v)));
+            }
+
+            // Every power of two prefix of the sequence falls in every 1D interval once, and
+            // the 2D samples in every cell of a 16 by 16 grid once
+            for dimension in 0..4 {
+                assert_one_per_cell(
+                    samples
+                        .iter()
+                        .map(|(values, _)| (values[dimension] * 256.0) as usize),
+                    256,
+                );
+            }
+            assert_one_per_cell(
+                samples
+                    .iter()
+                    .map(|(_, [x, y])| (x * 16.0) as usize + 16 * (y * 16.0) as usize),
+                256,
+            );
+        }
+    }
+}
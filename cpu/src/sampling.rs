@@ -0,0 +1,361 @@
+// Warps from uniform numbers in [0, 1) to common distributions. Every function takes its
+// random numbers as `u` and returns the sample with its density, per steradian for
+// directions and per unit area for points.
+
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI, TAU};
+
+use crate::math::Vec3;
+
+/// Direction on the unit sphere
+pub fn uniform_sphere(u: [f32; 2]) -> (Vec3, f32) {
+    let z = 1.0 - 2.0 * u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = TAU * u[1];
+
+    (Vec3::from(r * phi.cos(), r * phi.sin(), z), 1.0 / (4.0 * PI))
+}
+
+/// Direction on the hemisphere around +z
+#[allow(dead_code)]
+pub fn uniform_hemisphere(u: [f32; 2]) -> (Vec3, f32) {
+    let z = u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = TAU * u[1];
+
+    (Vec3::from(r * phi.cos(), r * phi.sin(), z), 1.0 / TAU)
+}
+
+/// Direction around +z with a density proportional to its cosine, the ideal for a Lambertian
+/// surface
+pub fn cosine_hemisphere(u: [f32; 2]) -> (Vec3, f32) {
+    // Project a uniform point on the disk up onto the hemisphere
+    let (disk, _) = uniform_disk(u);
+    let z = (1.0 - disk.x * disk.x - disk.y * disk.y).max(0.0).sqrt();
+
+    (Vec3::from(disk.x, disk.y, z), cosine_hemisphere_pdf(z))
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) / PI
+}
+
+/// Point on the unit disk in the xy plane. Uses Shirley and Chiu's concentric mapping, which
+/// keeps nearby `u` close together so stratified numbers stay stratified.
+pub fn uniform_disk(u: [f32; 2]) -> (Vec3, f32) {
+    let a = 2.0 * u[0] - 1.0;
+    let b = 2.0 * u[1] - 1.0;
+
+    if a == 0.0 && b == 0.0 {
+        return (Vec3::zero(), 1.0 / PI);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+
+    (Vec3::from(r * theta.cos(), r * theta.sin(), 0.0), 1.0 / PI)
+}
+
+/// Point on the triangle `a`, `b`, `c`
+pub fn uniform_triangle(u: [f32; 2], a: Vec3, b: Vec3, c: Vec3) -> (Vec3, f32) {
+    // Fold the square onto the lower triangle, half of it
+    let (s, t) = if u[0] + u[1] > 1.0 {
+        (1.0 - u[0], 1.0 - u[1])
+    } else {
+        (u[0], u[1])
+    };
+
+    let area = (b - a).cross(&(c - a)).length() * 0.5;
+
+    (a + (b - a) * s + (c - a) * t, 1.0 / area)
+}
+
+/// Direction within `cos_theta_max` of +z, like the directions that see a sphere from outside
+pub fn uniform_cone(u: [f32; 2], cos_theta_max: f32) -> (Vec3, f32) {
+    let z = 1.0 - u[0] * (1.0 - cos_theta_max);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = TAU * u[1];
+
+    (Vec3::from(r * phi.cos(), r * phi.sin(), z), uniform_cone_pdf(cos_theta_max))
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f32) -> f32 {
+    1.0 / (TAU * (1.0 - cos_theta_max))
+}
+
+/// Piecewise constant density over [0, 1) that follows `function`, one step per value
+pub struct Distribution1D {
+    function: Vec<f32>,
+    /// Running total of the normalized function, starting at 0 and ending at 1
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// A function that is zero everywhere gives a uniform density
+    pub fn new(function: Vec<f32>) -> Self {
+        let count = function.len() as f32;
+        let mut cdf = Vec::with_capacity(function.len() + 1);
+        cdf.push(0.0);
+        for value in &function {
+            cdf.push(cdf.last().unwrap() + value.max(0.0) / count);
+        }
+
+        let integral = *cdf.last().unwrap();
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 {
+                *value / integral
+            } else {
+                i as f32 / count
+            };
+        }
+
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Average of the function
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Point in [0, 1), its density and the step it is in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // The last step whose start is at or below `u`, skipping steps the density can't reach
+        let index = (self.cdf.partition_point(|&cdf| cdf <= u) - 1).min(self.function.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+        let x = ((index as f32 + offset) / self.function.len() as f32).min(1.0 - f32::EPSILON);
+
+        (x, self.pdf_at(index), index)
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        let index = ((x * self.function.len() as f32) as usize).min(self.function.len() - 1);
+        self.pdf_at(index)
+    }
+
+    fn pdf_at(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.function[index].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant density over [0, 1)^2 that follows a grid of values given row by row,
+/// sampled by picking a row and then a column within it
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f32], width: usize) -> Self {
+        let rows = function
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::integral).collect());
+
+        Self { rows, marginal }
+    }
+
+    /// Point in [0, 1)^2, x along the rows, and its density
+    pub fn sample(&self, u: [f32; 2]) -> ([f32; 2], f32) {
+        let (y, y_pdf, row) = self.marginal.sample(u[1]);
+        let (x, x_pdf, _) = self.rows[row].sample(u[0]);
+
+        ([x, y], x_pdf * y_pdf)
+    }
+
+    pub fn pdf(&self, point: [f32; 2]) -> f32 {
+        let row = ((point[1] * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(point[1]) * self.rows[row].pdf(point[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    const BINS: usize = 16;
+    const SAMPLES: usize = 65536;
+
+    /// Bins `SAMPLES` values of `warp`, mapped back to the unit square, and checks the counts
+    /// against `density` over the square with Pearson's chi-square test
+    fn chi_square(warp: impl Fn([f32; 2]) -> [f32; 2], density: impl Fn(f32, f32) -> f32) {
+        let mut rng = Rng::new(3, 0);
+        let mut counts = [[0.0f32; BINS]; BINS];
+        for _ in 0..SAMPLES {
+            let [a, b] = warp(rng.next_2d());
+            assert!((-1e-4..=1.0 + 1e-4).contains(&a), "{} is outside [0, 1]", a);
+            assert!((-1e-4..=1.0 + 1e-4).contains(&b), "{} is outside [0, 1]", b);
+
+            let bin = |x: f32| ((x * BINS as f32) as usize).min(BINS - 1);
+            counts[bin(a)][bin(b)] += 1.0;
+        }
+
+        // Integrate the density over each bin on a finer grid
+        let steps = 8;
+        let step = 1.0 / (BINS * steps) as f32;
+        let mut statistic = 0.0;
+        let mut degrees_of_freedom = 0;
+        for (i, row) in counts.iter().enumerate() {
+            for (j, &count) in row.iter().enumerate() {
+                let mut integral = 0.0;
+                for k in 0..steps {
+                    for l in 0..steps {
+                        let a = (i * steps + k) as f32 * step + step / 2.0;
+                        let b = (j * steps + l) as f32 * step + step / 2.0;
+                        integral += density(a, b) * step * step;
+                    }
+                }
+
+                let expected = integral * SAMPLES as f32;
+                if expected < 5.0 {
+                    assert!(count < 5.0 + 5.0 * expected.sqrt().max(1.0));
+                    continue;
+                }
+
+                statistic += (count - expected).powi(2) / expected;
+                degrees_of_freedom += 1;
+            }
+        }
+
+        // Far enough in the tail that only a wrong density fails, the samples are fixed anyway
+        let degrees_of_freedom = (degrees_of_freedom - 1) as f32;
+        let limit = degrees_of_freedom + 4.0 * (2.0 * degrees_of_freedom).sqrt();
+        assert!(statistic < limit, "chi-square {} over {}", statistic, limit);
+    }
+
+    fn azimuth(direction: Vec3) -> f32 {
+        (direction.y.atan2(direction.x) / TAU).rem_euclid(1.0)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn uniform_sphere_covers_the_sphere_evenly() {
+        chi_square(
+            |u| {
+                let (direction, pdf) = uniform_sphere(u);
+                assert_close(direction.length(), 1.0);
+                assert_close(pdf, 1.0 / (4.0 * PI));
+                [(1.0 - direction.z) / 2.0, azimuth(direction)]
+            },
+            |_, _| 1.0,
+        );
+    }
+
+    #[test]
+    fn uniform_hemisphere_covers_the_hemisphere_evenly() {
+        chi_square(
+            |u| {
+                let (direction, pdf) = uniform_hemisphere(u);
+                assert_close(direction.length(), 1.0);
+                assert_close(pdf, 1.0 / TAU);
+                [1.0 - direction.z, azimuth(direction)]
+            },
+            |_, _| 1.0,
+        );
+    }
+
+    #[test]
+    fn cosine_hemisphere_follows_the_cosine() {
+        // p(ω) = cos θ / π over dω = dz dφ makes z itself come out with density 2z
+        chi_square(
+            |u| {
+                let (direction, pdf) = cosine_hemisphere(u);
+                assert_close(direction.length(), 1.0);
+                assert_close(pdf, direction.z / PI);
+                assert_close(cosine_hemisphere_pdf(direction.z), pdf);
+                [1.0 - direction.z, azimuth(direction)]
+            },
+            |a, _| 2.0 * (1.0 - a),
+        );
+    }
+
+    #[test]
+    fn uniform_cone_covers_the_cone_evenly() {
+        for cos_theta_max in [-0.5, 0.0, 0.8, 0.999] {
+            chi_square(
+                |u| {
+                    let (direction, pdf) = uniform_cone(u, cos_theta_max);
+                    assert_close(direction.length(), 1.0);
+                    assert_close(pdf, uniform_cone_pdf(cos_theta_max));
+                    [
+                        (1.0 - direction.z) / (1.0 - cos_theta_max),
+                        azimuth(direction),
+                    ]
+                },
+                |_, _| 1.0,
+            );
+        }
+
+        // The whole sphere is a cone too
+        assert_close(uniform_cone_pdf(-1.0), 1.0 / (4.0 * PI));
+    }
+
+    #[test]
+    fn uniform_disk_covers_the_disk_evenly() {
+        // Uniform over the area means r² is uniform
+        chi_square(
+            |u| {
+                let (point, pdf) = uniform_disk(u);
+                assert_eq!(point.z, 0.0);
+                assert_close(pdf, 1.0 / PI);
+                [point.length().powi(2), azimuth(point)]
+            },
+            |_, _| 1.0,
+        );
+    }
+
+    #[test]
+    fn uniform_triangle_covers_the_triangle_evenly() {
+        let a = Vec3::from(1.0, 0.0, 0.0);
+        let b = Vec3::from(3.0, 1.0, 0.0);
+        let c = Vec3::from(0.0, 2.0, 1.0);
+        let (ab, ac) = (b - a, c - a);
+        let area = ab.cross(&ac).length() / 2.0;
+
+        chi_square(
+            |u| {
+                let (point, pdf) = uniform_triangle(u, a, b, c);
+                assert_close(pdf, 1.0 / area);
+
+                // Barycentric coordinates from the normal equations of point - a = s ab + t ac
+                let ap = point - a;
+                let (d00, d01, d11) = (ab.dot(&ab), ab.dot(&ac), ac.dot(&ac));
+                let (d20, d21) = (ap.dot(&ab), ap.dot(&ac));
+                let denominator = d00 * d11 - d01 * d01;
+                let s = (d11 * d20 - d01 * d21) / denominator;
+                let t = (d00 * d21 - d01 * d20) / denominator;
+                assert_close((ap - ab * s - ac * t).length(), 0.0);
+
+                // Even over the triangle, s + t has density 2(s + t) and splits evenly
+                let sum = s + t;
+                [sum * sum, if sum > 0.0 { t / sum } else { 0.0 }]
+            },
+            |_, _| 1.0,
+        );
+    }
+}
@@ -0,0 +1,1146 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::{fs, io};
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::bvh::Bvh;
+use crate::camera::Camera;
+use crate::hdr::{self, HdrError};
+use crate::lights::*;
+use crate::material::*;
+use crate::math::{Quat, Vec3};
+use crate::mesh::*;
+use crate::obj::{self, ObjError, ObjMaterial};
+use crate::object::*;
+use crate::ray::*;
+use crate::sampler::SamplerKind;
+use crate::sky::*;
+
+pub struct Scene {
+    pub objects: Vec<Object>,
+    pub bvh: Bvh,
+    /// Ids of the objects that give off light and are sampled like lights
+    pub emitters: Vec<usize>,
+    pub lights: Vec<Box<dyn Light>>,
+    pub camera: Camera,
+    pub sky: Sky,
+    pub settings: RenderSettings,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub max_depth: u32,
+    /// Number of render threads, 0 uses one per core
+    pub threads: usize,
+    pub tile_size: u32,
+    /// Seed for the random number generator, `None` gives a different image every run
+    pub seed: Option<u64>,
+    /// How the random numbers of a pixel's samples are spread out
+    pub sampler: SamplerKind,
+    /// Find hits with the BVH instead of testing every object
+    pub bvh: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 512,
+            height: 512,
+            samples: 20,
+            max_depth: 10,
+            threads: 0,
+            tile_size: 32,
+            seed: None,
+            sampler: SamplerKind::Independent,
+            bvh: true,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn threads(&self) -> usize {
+        if self.threads == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            self.threads
+        }
+    }
+
+    /// Check that the settings describe an image that can be rendered
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!(
+                "resolution must be at least 1x1, got {}x{}",
+                self.width, self.height
+            ));
+        }
+        if self.width.checked_mul(self.height).is_none() {
+            return Err(format!(
+                "resolution {}x{} is too large",
+                self.width, self.height
+            ));
+        }
+        if self.samples == 0 {
+            return Err("samples must be at least 1".to_string());
+        }
+        if self.max_depth == 0 {
+            return Err("max depth must be at least 1".to_string());
+        }
+        if self.tile_size == 0 {
+            return Err("tile size must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl Scene {
+    /// Closest hit between `t_min` and `t_max`
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.settings.bvh {
+            return self.bvh.hit(ray, t_min, t_max, |id, t_max| {
+                self.objects[id].hit(ray, t_min, t_max)
+            });
+        }
+
+        let mut hit_record = None;
+        let mut closest_so_far = t_max;
+
+        for object in &self.objects {
+            if let Some(temp_hit_record) = object.hit(ray, t_min, closest_so_far) {
+                closest_so_far = temp_hit_record.t;
+                hit_record = Some(temp_hit_record);
+            }
+        }
+
+        hit_record
+    }
+
+    /// Point on an emitter picked uniformly for next event estimation at `from`. Gives the
+    /// point, its outward normal, the emitter's id and the density of picking the point per
+    /// unit area.
+    pub fn sample_emitter(&self, from: Vec3, u: [f32; 2], pick: f32) -> Option<(Vec3, Vec3, usize, f32)> {
+        let pick = pick * self.emitters.len() as f32;
+        let emitter = *self.emitters.get(pick as usize).or(self.emitters.last())?;
+        // What is left of `pick` is uniform again, and picks the part of the emitter
+        let (point, normal, pdf) = self.objects[emitter].sample(from, u, pick.fract())?;
+
+        Some((point, normal, emitter, pdf / self.emitters.len() as f32))
+    }
+
+    /// Density of `sample_emitter` at `from` per unit area at the point `hit_record` is at
+    pub fn emitter_pdf(&self, from: Vec3, hit_record: &HitRecord) -> f32 {
+        let object = &self.objects[hit_record.object_id];
+
+        // The back of a one-sided emitter gives no light, so light sampling never counts there
+        if !object.is_emitter() || object.material.emitted(hit_record) == Vec3::zero() {
+            return 0.0;
+        }
+
+        object.pdf(from, hit_record.point, hit_record.geometric_normal) / self.emitters.len() as f32
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Obj(ObjError),
+    Hdr(HdrError),
+}
+
+impl SceneError {
+    fn parse(path: &str, source: &str, offset: usize, message: impl Into<String>) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+        Self::Parse {
+            path: path.to_string(),
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path, error),
+            Self::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            Self::Obj(error) => write!(f, "{}", error),
+            Self::Hdr(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: Option<Spanned<Camera>>,
+    #[serde(default)]
+    sky: Option<Spanned<SkyDescription>>,
+    /// Deprecated, the same as `[sky]` with `type = "color"`
+    #[serde(default)]
+    sky_color: Option<Spanned<Vec3>>,
+    #[serde(default)]
+    render: RenderSettings,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDescription>>,
+    #[serde(default)]
+    spheres: Vec<SphereDescription>,
+    #[serde(default)]
+    planes: Vec<PlaneDescription>,
+    #[serde(default)]
+    quads: Vec<QuadDescription>,
+    #[serde(default)]
+    meshes: Vec<MeshDescription>,
+    #[serde(default)]
+    models: Vec<ModelDescription>,
+    /// Glowing spheres, sampled like any other emitter. Not the same as `[[lights]]` with
+    /// `type = "point"`, see `PointLightDescription`.
+    #[serde(default)]
+    point_lights: Vec<PointLightDescription>,
+    #[serde(default)]
+    lights: Vec<Spanned<LightDescription>>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SkyDescription {
+    Color {
+        color: Vec3,
+    },
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+        #[serde(default = "default_up")]
+        up: Vec3,
+    },
+    /// Equirectangular Radiance .hdr image
+    Map {
+        /// Relative to the scene file
+        path: String,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        /// Degrees around x, then y, then z
+        #[serde(default)]
+        rotation: Option<Vec3>,
+    },
+}
+
+fn default_sky_color() -> Vec3 {
+    Vec3::from(0.5, 0.7, 1.0)
+}
+
+fn default_up() -> Vec3 {
+    Vec3::from(0.0, 1.0, 0.0)
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Diffuse {
+        color: Vec3,
+    },
+    Metal {
+        color: Vec3,
+        #[serde(default)]
+        roughness: f32,
+    },
+    Dielectric {
+        #[serde(default = "Vec3::one")]
+        color: Vec3,
+        refractive_index: f32,
+    },
+    Pbr {
+        #[serde(default = "Vec3::one")]
+        base_color: Vec3,
+        #[serde(default)]
+        metallic: f32,
+        #[serde(default = "default_roughness")]
+        roughness: f32,
+        #[serde(default = "default_specular")]
+        specular: f32,
+        #[serde(default = "Vec3::zero")]
+        emission: Vec3,
+    },
+    Light {
+        color: Vec3,
+    },
+    /// Only gives off light, from the front unless `two_sided`
+    Emissive {
+        radiance: Vec3,
+        #[serde(default)]
+        two_sided: bool,
+    },
+}
+
+fn default_roughness() -> f32 {
+    0.5
+}
+
+fn default_specular() -> f32 {
+    0.5
+}
+
+impl MaterialDescription {
+    /// Check the parameters that only make sense in a range
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            Self::Metal { roughness, .. } => check_unit("roughness", roughness),
+            Self::Dielectric {
+                refractive_index, ..
+            } if refractive_index.is_nan() || refractive_index <= 0.0 => Err(format!(
+                "refractive index must be positive, got {}",
+                refractive_index
+            )),
+            Self::Pbr {
+                metallic,
+                roughness,
+                emission,
+                ..
+            } => {
+                check_unit("metallic", metallic)?;
+                check_unit("roughness", roughness)?;
+                check_color("emission", emission)
+            }
+            Self::Light { color } => check_color("light color", color),
+            Self::Emissive { radiance, .. } => check_color("radiance", radiance),
+            _ => Ok(()),
+        }
+    }
+
+    fn build(&self) -> Box<dyn Material> {
+        match self {
+            Self::Diffuse { color } => Diffuse::boxed(*color),
+            Self::Metal { color, roughness } => Metal::boxed(*color, *roughness),
+            Self::Dielectric {
+                color,
+                refractive_index,
+            } => Dielectric::boxed(*color, *refractive_index),
+            Self::Pbr {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                emission,
+            } => Box::new(Pbr {
+                base_color: *base_color,
+                metallic: *metallic,
+                roughness: *roughness,
+                specular: *specular,
+                emission: *emission,
+            }),
+            Self::Light { color } => Box::new(PointLightMaterial { color: *color }),
+            Self::Emissive {
+                radiance,
+                two_sided,
+            } => Emissive::boxed(*radiance, *two_sided),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDescription {
+    center: Vec3,
+    radius: Spanned<f32>,
+    material: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDescription {
+    point: Vec3,
+    normal: Spanned<Vec3>,
+    material: Spanned<String>,
+}
+
+/// Parallelogram from `corner` along the edges `u` and `v`, the front faces along `u` cross `v`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuadDescription {
+    corner: Vec3,
+    u: Vec3,
+    v: Spanned<Vec3>,
+    material: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDescription {
+    #[serde(default)]
+    position: Option<Vec3>,
+    /// Degrees around x, then y, then z
+    #[serde(default)]
+    rotation: Option<Vec3>,
+    #[serde(default)]
+    scale: Option<Spanned<Vec3>>,
+    vertices: Vec<Vec3>,
+    indices: Spanned<Vec<usize>>,
+    /// One per vertex for smooth shading
+    #[serde(default)]
+    normals: Option<Spanned<Vec<Vec3>>>,
+    material: Spanned<String>,
+}
+
+/// Wavefront OBJ file, each group and material in it becomes an object
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelDescription {
+    /// Relative to the scene file
+    path: Spanned<String>,
+    #[serde(default)]
+    position: Option<Vec3>,
+    /// Degrees around x, then y, then z
+    #[serde(default)]
+    rotation: Option<Vec3>,
+    #[serde(default)]
+    scale: Option<Spanned<Vec3>>,
+    /// Used for faces before any `usemtl`
+    #[serde(default)]
+    material: Option<Spanned<String>>,
+}
+
+/// Sphere with a light material, the name is older than `[[lights]]`. It has a size, so it
+/// casts soft shadows, shows up in the image and in reflections and is hit by rays like any
+/// other object. A `[[lights]]` point light is an infinitely small point that is only reached
+/// by sampling it, isn't visible, and falls off with the inverse square of the distance.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightDescription {
+    position: Vec3,
+    radius: Spanned<f32>,
+    color: Spanned<Vec3>,
+    /// Deprecated and ignored, glowing spheres have no range. A `[[lights]]` point light can
+    /// have one.
+    #[serde(default, rename = "range")]
+    _range: Option<IgnoredAny>,
+}
+
+/// Light without a body, `color` is multiplied by `intensity`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDescription {
+    Point {
+        position: Vec3,
+        #[serde(default = "Vec3::one")]
+        color: Vec3,
+        intensity: f32,
+        #[serde(default)]
+        range: Option<f32>,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        #[serde(default = "Vec3::one")]
+        color: Vec3,
+        intensity: f32,
+        #[serde(default)]
+        range: Option<f32>,
+        /// Degrees from the axis to the edge of the cone
+        angle: f32,
+        /// Degrees at the edge of the cone over which the light fades out
+        #[serde(default)]
+        falloff: f32,
+    },
+    #[serde(alias = "sun")]
+    Directional {
+        /// Direction the light travels in
+        direction: Vec3,
+        #[serde(default = "Vec3::one")]
+        color: Vec3,
+        intensity: f32,
+    },
+}
+
+impl LightDescription {
+    fn build(&self) -> Result<Box<dyn Light>, String> {
+        match *self {
+            Self::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => {
+                check_intensity(intensity)?;
+                check_color("light color", color)?;
+                check_range(range)?;
+
+                Ok(Box::new(PointLight {
+                    position,
+                    intensity: color * intensity,
+                    range,
+                }))
+            }
+            Self::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                angle,
+                falloff,
+            } => {
+                check_intensity(intensity)?;
+                check_color("light color", color)?;
+                check_range(range)?;
+                check_direction(direction)?;
+
+                if !(angle > 0.0 && angle < 180.0) {
+                    return Err(format!(
+                        "spot angle must be between 0 and 180 degrees, got {}",
+                        angle
+                    ));
+                }
+                if !(falloff >= 0.0 && falloff <= angle) {
+                    return Err(format!(
+                        "spot falloff must be between 0 and the angle, got {}",
+                        falloff
+                    ));
+                }
+
+                Ok(Box::new(SpotLight::new(
+                    position,
+                    direction,
+                    color * intensity,
+                    range,
+                    angle,
+                    falloff,
+                )))
+            }
+            Self::Directional {
+                direction,
+                color,
+                intensity,
+            } => {
+                check_intensity(intensity)?;
+                check_color("light color", color)?;
+                check_direction(direction)?;
+
+                Ok(Box::new(DirectionalLight {
+                    direction: direction.normalized(),
+                    irradiance: color * intensity,
+                }))
+            }
+        }
+    }
+}
+
+fn check_unit(name: &str, value: f32) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{} must be between 0 and 1, got {}", name, value));
+    }
+
+    Ok(())
+}
+
+fn check_color(name: &str, color: Vec3) -> Result<(), String> {
+    if [color.x, color.y, color.z]
+        .iter()
+        .any(|channel| channel.is_nan() || *channel < 0.0)
+    {
+        return Err(format!(
+            "{} can't be negative, got {:?}",
+            name,
+            [color.x, color.y, color.z]
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_intensity(intensity: f32) -> Result<(), String> {
+    if intensity.is_nan() || intensity < 0.0 {
+        return Err(format!("light intensity can't be negative, got {}", intensity));
+    }
+
+    Ok(())
+}
+
+fn check_range(range: Option<f32>) -> Result<(), String> {
+    match range {
+        Some(range) if range.is_nan() || range <= 0.0 => {
+            Err(format!("light range must be positive, got {}", range))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_direction(direction: Vec3) -> Result<(), String> {
+    if direction.length() == 0.0 {
+        return Err("light direction can't be zero".to_string());
+    }
+
+    Ok(())
+}
+
+/// Read and build the scene at `path`
+pub fn load(path: &str) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path).map_err(|error| SceneError::Io {
+        path: path.to_string(),
+        error,
+    })?;
+
+    parse(&source, path)
+}
+
+/// Build a scene from TOML source, `path` is only used in error messages
+pub fn parse(source: &str, path: &str) -> Result<Scene, SceneError> {
+    let file: SceneFile = toml::from_str(source).map_err(|error| {
+        let offset = error.span().map_or(0, |span| span.start);
+        SceneError::parse(path, source, offset, error.message())
+    })?;
+
+    let camera = match &file.camera {
+        Some(camera) => {
+            camera.get_ref().validate().map_err(|message| {
+                SceneError::parse(path, source, camera.span().start, message)
+            })?;
+
+            let mut camera = *camera.get_ref();
+            if let Some(distance) = camera.viewport_distance.take() {
+                camera.fov = 2.0 * (0.5 / distance).atan().to_degrees();
+            }
+            camera
+        }
+        None => Camera::default(),
+    };
+
+    // Report the first bad material in the file, not whichever the map gives first
+    let invalid_material = file
+        .materials
+        .values()
+        .filter_map(|material| {
+            let message = material.get_ref().validate().err()?;
+            Some((material.span().start, message))
+        })
+        .min_by_key(|&(offset, _)| offset);
+
+    if let Some((offset, message)) = invalid_material {
+        return Err(SceneError::parse(path, source, offset, message));
+    }
+
+    if let (Some(_), Some(sky_color)) = (&file.sky, &file.sky_color) {
+        return Err(SceneError::parse(
+            path,
+            source,
+            sky_color.span().start,
+            "sky_color is an old way to write [sky], give only one of them",
+        ));
+    }
+
+    let sky = match &file.sky {
+        Some(sky) => {
+            let error = |message: &str| SceneError::parse(path, source, sky.span().start, message);
+
+            match sky.get_ref() {
+                SkyDescription::Color { color } => Sky::Color(*color),
+                SkyDescription::Gradient { bottom, top, up } => {
+                    if up.length() == 0.0 {
+                        return Err(error("sky up direction can't be zero"));
+                    }
+
+                    Sky::Gradient {
+                        bottom: *bottom,
+                        top: *top,
+                        up: up.normalized(),
+                    }
+                }
+                SkyDescription::Map {
+                    path: map_path,
+                    intensity,
+                    rotation,
+                } => {
+                    if intensity.is_nan() || *intensity < 0.0 {
+                        return Err(error("sky intensity can't be negative"));
+                    }
+
+                    let map_path = Path::new(path)
+                        .parent()
+                        .unwrap_or(Path::new(""))
+                        .join(map_path);
+                    let image = hdr::load(&map_path).map_err(SceneError::Hdr)?;
+
+                    if image.width == 0 || image.height == 0 {
+                        return Err(error("sky map has no pixels"));
+                    }
+
+                    let rotation = rotation.unwrap_or(Vec3::zero());
+                    let rotation = Quat::from_euler(Vec3::from(
+                        rotation.x.to_radians(),
+                        rotation.y.to_radians(),
+                        rotation.z.to_radians(),
+                    ));
+
+                    Sky::Map(EnvironmentMap::new(image, *intensity, rotation))
+                }
+            }
+        }
+        None => Sky::Color(
+            file.sky_color
+                .as_ref()
+                .map_or_else(default_sky_color, |color| *color.get_ref()),
+        ),
+    };
+
+    let material = |name: &Spanned<String>| {
+        file.materials
+            .get(name.get_ref())
+            .map(|material| material.get_ref().build())
+            .ok_or_else(|| {
+                SceneError::parse(
+                    path,
+                    source,
+                    name.span().start,
+                    format!("unknown material `{}`", name.get_ref()),
+                )
+            })
+    };
+
+    let transform = |position: Option<Vec3>, rotation: Option<Vec3>, scale: &Option<Spanned<Vec3>>| {
+        let transform = Transform::from(
+            position.unwrap_or(Vec3::zero()),
+            rotation.unwrap_or(Vec3::zero()),
+            scale.as_ref().map_or(Vec3::one(), |scale| *scale.get_ref()),
+        );
+
+        match scale {
+            Some(scale) if transform.matrix().inverse().is_none() => Err(SceneError::parse(
+                path,
+                source,
+                scale.span().start,
+                "scale components can't be 0",
+            )),
+            _ => Ok(transform),
+        }
+    };
+
+    let radius = |radius: &Spanned<f32>| match *radius.get_ref() {
+        value if value > 0.0 => Ok(value),
+        _ => Err(SceneError::parse(
+            path,
+            source,
+            radius.span().start,
+            "radius must be positive",
+        )),
+    };
+
+    let mut objects = Vec::new();
+
+    for sphere in &file.spheres {
+        objects.push(Object::sphere(
+            sphere.center,
+            radius(&sphere.radius)?,
+            material(&sphere.material)?,
+            objects.len(),
+        ));
+    }
+
+    for plane in &file.planes {
+        if plane.normal.get_ref().length() == 0.0 {
+            return Err(SceneError::parse(
+                path,
+                source,
+                plane.normal.span().start,
+                "plane normal can't be zero",
+            ));
+        }
+
+        objects.push(Object::plane(
+            plane.point,
+            plane.normal.get_ref().normalized(),
+            material(&plane.material)?,
+            objects.len(),
+        ));
+    }
+
+    for quad in &file.quads {
+        if quad.u.cross(quad.v.get_ref()).length() == 0.0 {
+            return Err(SceneError::parse(
+                path,
+                source,
+                quad.v.span().start,
+                "quad edges must not be parallel or zero",
+            ));
+        }
+
+        objects.push(Object::quad(
+            quad.corner,
+            quad.u,
+            *quad.v.get_ref(),
+            material(&quad.material)?,
+            objects.len(),
+        ));
+    }
+
+    for mesh in &file.meshes {
+        let indices = mesh.indices.get_ref();
+
+        if indices.len() % 3 != 0 {
+            return Err(SceneError::parse(
+                path,
+                source,
+                mesh.indices.span().start,
+                "mesh indices must come in groups of three",
+            ));
+        }
+
+        if let Some(index) = indices.iter().find(|&&i| i >= mesh.vertices.len()) {
+            return Err(SceneError::parse(
+                path,
+                source,
+                mesh.indices.span().start,
+                format!(
+                    "mesh index {} is out of range for {} vertices",
+                    index,
+                    mesh.vertices.len()
+                ),
+            ));
+        }
+
+        let normals = match &mesh.normals {
+            Some(normals) if normals.get_ref().len() != mesh.vertices.len() => {
+                return Err(SceneError::parse(
+                    path,
+                    source,
+                    normals.span().start,
+                    format!(
+                        "mesh has {} normals for {} vertices, give one per vertex",
+                        normals.get_ref().len(),
+                        mesh.vertices.len()
+                    ),
+                ));
+            }
+            Some(normals) => normals.get_ref().clone(),
+            None => Vec::new(),
+        };
+
+        objects.push(Object::from_mesh(
+            transform(mesh.position, mesh.rotation, &mesh.scale)?,
+            Mesh::new(mesh.vertices.clone(), indices.clone(), normals),
+            material(&mesh.material)?,
+            objects.len(),
+        ));
+    }
+
+    for model in &file.models {
+        let model_path = Path::new(path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(model.path.get_ref());
+        let obj = obj::load(&model_path).map_err(SceneError::Obj)?;
+        let model_transform = transform(model.position, model.rotation, &model.scale)?;
+
+        for group in obj.groups {
+            let material = match &group.material {
+                Some((name, line)) => obj
+                    .materials
+                    .get(name)
+                    .map(ObjMaterial::build)
+                    .or_else(|| file.materials.get(name).map(|material| material.get_ref().build()))
+                    .ok_or_else(|| {
+                        SceneError::Obj(ObjError::Parse {
+                            path: obj.path.clone(),
+                            line: *line,
+                            message: format!("unknown material `{}`", name),
+                        })
+                    })?,
+                None => match &model.material {
+                    Some(name) => material(name)?,
+                    None => {
+                        return Err(SceneError::parse(
+                            path,
+                            source,
+                            model.path.span().start,
+                            "model has faces without usemtl, give it a material",
+                        ))
+                    }
+                },
+            };
+
+            objects.push(Object::from_mesh(
+                model_transform,
+                group.mesh,
+                material,
+                objects.len(),
+            ));
+        }
+    }
+
+    for light in &file.point_lights {
+        check_color("light color", *light.color.get_ref()).map_err(|message| {
+            SceneError::parse(path, source, light.color.span().start, message)
+        })?;
+
+        objects.push(Object::point_light(
+            light.position,
+            radius(&light.radius)?,
+            *light.color.get_ref(),
+            objects.len(),
+        ));
+    }
+
+    let lights = file
+        .lights
+        .iter()
+        .map(|light| {
+            light.get_ref().build().map_err(|message| {
+                SceneError::parse(path, source, light.span().start, message)
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Scene {
+        bvh: Bvh::new(objects.iter().map(Object::bounding_box)),
+        emitters: objects
+            .iter()
+            .filter(|object| object.is_emitter())
+            .map(|object| object.id)
+            .collect(),
+        lights,
+        objects,
+        camera,
+        sky,
+        settings: file.render,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATERIAL: &str = "[materials.grey]\ntype = \"diffuse\"\ncolor = [0.5, 0.5, 0.5]\n\n";
+
+    /// The error message for `source`, which should fail to load
+    fn error(source: &str) -> String {
+        match parse(source, "test.toml") {
+            Ok(_) => panic!("loaded a broken scene"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn unknown_fields_give_their_position() {
+        let source = format!(
+            "{}[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\ncolour = [1.0, 0.0, 0.0]\nmaterial = \"grey\"\n",
+            MATERIAL
+        );
+
+        assert_eq!(
+            error(&source),
+            "test.toml:8:1: unknown field `colour`, expected one of `center`, `radius`, `material`"
+        );
+    }
+
+    #[test]
+    fn wrong_types_give_their_position() {
+        let source = format!(
+            "{}[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = \"big\"\nmaterial = \"grey\"\n",
+            MATERIAL
+        );
+
+        assert_eq!(
+            error(&source),
+            "test.toml:7:10: invalid type: string \"big\", expected f32"
+        );
+    }
+
+    #[test]
+    fn unknown_materials_give_their_position() {
+        let source = "[materials.grey]\ntype = \"chalk\"\n";
+        assert!(error(source).starts_with("test.toml:2:8: unknown variant `chalk`"));
+
+        let source = format!(
+            "{}[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\nmaterial = \"gray\"\n",
+            MATERIAL
+        );
+        assert_eq!(error(&source), "test.toml:8:12: unknown material `gray`");
+    }
+
+    #[test]
+    fn zero_scale_gives_its_position() {
+        let source = format!(
+            "{}[[meshes]]\nscale = [1.0, 0.0, 1.0]\nvertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]\nindices = [0, 1, 2]\nmaterial = \"grey\"\n",
+            MATERIAL
+        );
+
+        assert_eq!(error(&source), "test.toml:6:9: scale components can't be 0");
+    }
+
+    #[test]
+    fn sky_color_still_sets_a_color_sky() {
+        let scene = parse("sky_color = [0.1, 0.2, 0.3]", "test.toml").unwrap();
+
+        assert!(matches!(scene.sky, Sky::Color(color) if color == Vec3::from(0.1, 0.2, 0.3)));
+    }
+
+    #[test]
+    fn sky_color_and_sky_clash() {
+        let source =
+            "sky_color = [0.1, 0.2, 0.3]\n\n[sky]\ntype = \"color\"\ncolor = [1.0, 1.0, 1.0]\n";
+
+        assert_eq!(
+            error(source),
+            "test.toml:1:13: sky_color is an old way to write [sky], give only one of them"
+        );
+    }
+
+    #[test]
+    fn viewport_distance_still_sets_the_field_of_view() {
+        let fov = |source: &str| parse(source, "test.toml").unwrap().camera.fov;
+
+        // Older scenes only had the distance to a viewport one unit tall
+        let source = "[camera]\nposition = [0.0, 0.0, 0.0]\nviewport_distance = 1.0\n";
+        assert!((fov(source) - Camera::default().fov).abs() < 1e-4);
+        assert!((fov("[camera]\nviewport_distance = 0.5\n") - 90.0).abs() < 1e-4);
+
+        assert_eq!(
+            error("[camera]\nviewport_distance = 0.0\n"),
+            "test.toml:1:1: viewport distance must be positive, got 0"
+        );
+    }
+
+    #[test]
+    fn point_light_range_is_still_accepted() {
+        let source = "[[point_lights]]\nposition = [0.0, 1.7, 4.0]\nradius = 0.7\nrange = 100.0\ncolor = [10.0, 10.0, 10.0]\n";
+        let scene = parse(source, "test.toml").unwrap();
+
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(scene.emitters.len(), 1);
+        assert!(scene.lights.is_empty());
+    }
+
+    #[test]
+    fn roughness_and_metallic_stay_between_0_and_1() {
+        assert_eq!(
+            error(
+                "[materials.steel]\ntype = \"metal\"\ncolor = [0.8, 0.8, 0.8]\nroughness = 1.5\n"
+            ),
+            "test.toml:1:1: roughness must be between 0 and 1, got 1.5"
+        );
+        assert_eq!(
+            error("[materials.ok]\ntype = \"pbr\"\n\n[materials.gold]\ntype = \"pbr\"\nmetallic = -0.1\n"),
+            "test.toml:4:1: metallic must be between 0 and 1, got -0.1"
+        );
+        assert_eq!(
+            error("[materials.clay]\ntype = \"pbr\"\nroughness = 2.0\n\n[materials.gold]\ntype = \"pbr\"\nmetallic = 3.0\n"),
+            "test.toml:1:1: roughness must be between 0 and 1, got 2"
+        );
+
+        let source =
+            "[materials.steel]\ntype = \"metal\"\ncolor = [0.8, 0.8, 0.8]\nroughness = 1.0\n";
+        assert!(parse(source, "test.toml").is_ok());
+    }
+
+    #[test]
+    fn refractive_index_must_be_positive() {
+        for index in ["0.0", "-1.5", "nan"] {
+            let source = format!(
+                "{}[materials.glass]\ntype = \"dielectric\"\nrefractive_index = {}\n",
+                MATERIAL, index
+            );
+            assert_eq!(
+                error(&source),
+                format!(
+                    "test.toml:5:1: refractive index must be positive, got {}",
+                    index.parse::<f32>().unwrap()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn negative_light_is_rejected() {
+        let cases = [
+            (
+                "[materials.lamp]\ntype = \"emissive\"\nradiance = [1.0, -1.0, 1.0]\n",
+                "test.toml:1:1: radiance can't be negative, got [1.0, -1.0, 1.0]",
+            ),
+            (
+                "[materials.lamp]\ntype = \"pbr\"\nemission = [0.0, 0.0, -0.5]\n",
+                "test.toml:1:1: emission can't be negative, got [0.0, 0.0, -0.5]",
+            ),
+            (
+                "[materials.lamp]\ntype = \"light\"\ncolor = [-4.0, 4.0, 4.0]\n",
+                "test.toml:1:1: light color can't be negative, got [-4.0, 4.0, 4.0]",
+            ),
+            (
+                "[[lights]]\ntype = \"point\"\nposition = [0.0, 0.0, 0.0]\nintensity = -2.0\n",
+                "test.toml:1:1: light intensity can't be negative, got -2",
+            ),
+            (
+                "[[lights]]\ntype = \"sun\"\ndirection = [0.0, -1.0, 0.0]\ncolor = [1.0, -1.0, 1.0]\nintensity = 2.0\n",
+                "test.toml:1:1: light color can't be negative, got [1.0, -1.0, 1.0]",
+            ),
+            (
+                "[[point_lights]]\nposition = [0.0, 0.0, 0.0]\nradius = 1.0\ncolor = [1.0, 1.0, -1.0]\n",
+                "test.toml:4:9: light color can't be negative, got [1.0, 1.0, -1.0]",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(error(source), expected);
+        }
+    }
+
+    #[test]
+    fn quads_only_glow_from_the_front() {
+        // Facing +z, along u cross v
+        let quad = |two_sided: bool| {
+            format!(
+                "[materials.lamp]\ntype = \"emissive\"\nradiance = [1.0, 1.0, 1.0]\ntwo_sided = {}\n\n\
+                 [[quads]]\ncorner = [0.0, 0.0, 0.0]\nu = [1.0, 0.0, 0.0]\nv = [0.0, 1.0, 0.0]\nmaterial = \"lamp\"\n",
+                two_sided
+            )
+        };
+        let look = |scene: &Scene, z: f32| {
+            let ray = Ray {
+                origin: Vec3::from(0.5, 0.5, z),
+                direction: Vec3::from(0.0, 0.0, -z),
+            };
+            let hit_record = scene.hit(&ray, 0.0, f32::MAX).unwrap();
+            let emitted = scene.objects[hit_record.object_id]
+                .material
+                .emitted(&hit_record);
+            (emitted, scene.emitter_pdf(ray.origin, &hit_record))
+        };
+
+        let one_sided = parse(&quad(false), "test.toml").unwrap();
+        assert_eq!(look(&one_sided, 1.0), (Vec3::one(), 1.0));
+        assert_eq!(look(&one_sided, -1.0), (Vec3::zero(), 0.0));
+
+        let two_sided = parse(&quad(true), "test.toml").unwrap();
+        assert_eq!(look(&two_sided, -1.0), (Vec3::one(), 1.0));
+    }
+}
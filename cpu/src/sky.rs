@@ -0,0 +1,233 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::hdr::HdrImage;
+use crate::material::luminance;
+use crate::math::{Quat, Vec3};
+use crate::sampling::Distribution2D;
+
+/// What rays that miss everything see, it lights the scene from all around
+#[derive(Debug)]
+pub enum Sky {
+    Color(Vec3),
+    /// Blend from `bottom` straight down to `top` straight up
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+        up: Vec3,
+    },
+    Map(EnvironmentMap),
+}
+
+impl Sky {
+    /// Light arriving from `direction`
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Self::Color(color) => *color,
+            Self::Gradient { bottom, top, up } => {
+                let t = 0.5 * (direction.normalized().dot(up) + 1.0);
+                *bottom * (1.0 - t) + *top * t
+            }
+            Self::Map(map) => map.radiance(direction),
+        }
+    }
+
+    /// Direction to sample for next event estimation and its density per steradian, `None`
+    /// for skies that are only found by rays that miss everything
+    pub fn sample(&self, u: [f32; 2]) -> Option<(Vec3, f32)> {
+        match self {
+            Self::Map(map) => map.sample(u),
+            _ => None,
+        }
+    }
+
+    /// Density of `sample` picking `direction`
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Self::Map(map) => map.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Equirectangular image around the scene. Before `rotation`, +y is up and the middle of the
+/// image faces +z.
+pub struct EnvironmentMap {
+    image: HdrImage,
+    intensity: f32,
+    rotation: Quat,
+    /// Follows the brightness of the pixels, to send more samples towards bright ones
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage, intensity: f32, rotation: Quat) -> Self {
+        // Rows near the poles cover less of the sphere, weigh them by their sine
+        let weights = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let theta = ((i / image.width) as f32 + 0.5) / image.height as f32 * PI;
+                luminance(*pixel) * theta.sin()
+            })
+            .collect::<Vec<_>>();
+        let distribution = Distribution2D::new(&weights, image.width);
+
+        Self {
+            image,
+            intensity,
+            rotation,
+            distribution,
+        }
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let [u, v] = self.to_image(direction);
+        let x = ((u * self.image.width as f32) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f32) as usize).min(self.image.height - 1);
+
+        self.image.pixels[y * self.image.width + x] * self.intensity
+    }
+
+    pub fn sample(&self, u: [f32; 2]) -> Option<(Vec3, f32)> {
+        let ([u, v], pdf) = self.distribution.sample(u);
+        let (sin_theta, cos_theta) = (v * PI).sin_cos();
+        let (sin_phi, cos_phi) = ((u - 0.5) * TAU).sin_cos();
+
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        let direction = Vec3::from(sin_theta * sin_phi, cos_theta, sin_theta * cos_phi);
+
+        // The image covers 2 pi by pi radians, stretched by the sine towards the poles
+        Some((
+            self.rotation.rotate(&direction),
+            pdf / (2.0 * PI * PI * sin_theta),
+        ))
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let point = self.to_image(direction);
+        let sin_theta = (point[1] * PI).sin();
+
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(point) / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Position of `direction` in the image, from 0 to 1 across and down
+    fn to_image(&self, direction: Vec3) -> [f32; 2] {
+        let direction = self.rotation.inverse().rotate(&direction).normalized();
+
+        [
+            0.5 + direction.x.atan2(direction.z) / TAU,
+            // acos of y loses most of its precision near the poles, where sin theta is small
+            direction.x.hypot(direction.z).atan2(direction.y) / PI,
+        ]
+    }
+}
+
+impl std::fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("width", &self.image.width)
+            .field("height", &self.image.height)
+            .field("intensity", &self.intensity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 8;
+
+    /// A bright spot, brighter upper half, a gradient across and a black bottom row, turned
+    /// away from the image's own axes
+    fn map() -> EnvironmentMap {
+        let pixels = (0..WIDTH * HEIGHT)
+            .map(|i| match (i % WIDTH, i / WIDTH) {
+                (3, 2) => Vec3::from(50.0, 40.0, 30.0),
+                (_, 7) => Vec3::zero(),
+                (x, y) => Vec3::one() * (1.0 + x as f32 * 0.25) * if y < 4 { 2.0 } else { 0.5 },
+            })
+            .collect();
+        let image = HdrImage {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels,
+        };
+
+        EnvironmentMap::new(image, 1.5, Quat::from_euler(Vec3::from(0.3, 1.2, -0.4)))
+    }
+
+    /// World direction `theta` down from the map's up and `phi` around it from its middle
+    fn direction(map: &EnvironmentMap, theta: f32, phi: f32) -> Vec3 {
+        map.rotation.rotate(&Vec3::from(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        ))
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_the_sphere() {
+        let map = map();
+        // Several steps per pixel, a solid angle element is sin theta dtheta dphi
+        let (rows, columns) = (HEIGHT * 16, WIDTH * 16);
+        let (d_theta, d_phi) = (PI / rows as f32, TAU / columns as f32);
+
+        let mut integral = 0.0;
+        for row in 0..rows {
+            let theta = (row as f32 + 0.5) * d_theta;
+            for column in 0..columns {
+                let phi = (column as f32 + 0.5) * d_phi - PI;
+                integral += map.pdf(direction(&map, theta, phi)) * theta.sin() * d_theta * d_phi;
+            }
+        }
+
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+        assert_eq!(map.pdf(direction(&map, 0.97 * PI, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn samples_match_pdf_and_estimate_the_light() {
+        let map = map();
+        let mut rng = Rng::new(11, 0);
+
+        // Exact light over the sphere, every pixel times its solid angle
+        let expected = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let row = (i / WIDTH) as f32;
+                let solid_angle = TAU / WIDTH as f32
+                    * ((row / HEIGHT as f32 * PI).cos() - ((row + 1.0) / HEIGHT as f32 * PI).cos());
+                luminance(map.image.pixels[i]) * map.intensity * solid_angle
+            })
+            .sum::<f32>();
+
+        let samples = 1 << 16;
+        let mut estimate = 0.0;
+        for _ in 0..samples {
+            let (direction, pdf) = map.sample(rng.next_2d()).unwrap();
+            let lookup = map.pdf(direction);
+
+            assert!((direction.length() - 1.0).abs() < 1e-4);
+            assert!((lookup - pdf).abs() <= 1e-3 * pdf, "{} != {}", lookup, pdf);
+            estimate += luminance(map.radiance(direction)) / pdf;
+        }
+        let estimate = estimate / samples as f32;
+
+        assert!(
+            (estimate - expected).abs() < 0.01 * expected,
+            "{} != {}",
+            estimate,
+            expected
+        );
+    }
+}
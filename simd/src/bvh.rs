@@ -0,0 +1,384 @@
+use crate::math::*;
+use crate::ray::*;
+
+/// Number of centroid bins tried per axis when looking for the cheapest split
+const SAH_BINS: usize = 12;
+/// Cost of visiting a node relative to intersecting one object
+const TRAVERSAL_COST: f32 = 0.5;
+/// Deepest level of the tree, below it objects stay in leaves however many there are
+const MAX_DEPTH: usize = 64;
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Box that contains nothing, the identity for `union`
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::from(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3::from(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::empty(), |aabb, point| aabb.grow(point))
+    }
+
+    pub fn grow(&self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(&point),
+            max: self.max.max(&point),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    /// Grow the box by a small margin so rounding in the slab test can't miss a surface that
+    /// touches its sides
+    pub fn padded(&self) -> Self {
+        let margin = (self.max - self.min) * 1e-4 + Vec3::one() * 1e-5;
+        Self {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        if size.x() < 0.0 || size.y() < 0.0 || size.z() < 0.0 {
+            return 0.0;
+        }
+        2.0 * (size.x() * size.y() + size.y() * size.z() + size.z() * size.x())
+    }
+
+    /// Distance along the ray to where it enters the box, if it does so between `t_min` and
+    /// `t_max`. `inverse_direction` is `1.0 / ray.direction` per component.
+    pub fn hit(&self, ray: &Ray, inverse_direction: &Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let mut enter = t_min;
+        let mut exit = t_max;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction[axis];
+
+            // f32::min/max ignore the NaN from 0 * inf when the origin is on a slab
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+
+        if enter <= exit {
+            Some(enter)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    /// First object in `Bvh::objects` for leaves, index of the first child for interior nodes.
+    /// The second child always follows the first.
+    start: usize,
+    /// Number of objects in a leaf, 0 for interior nodes
+    count: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Item {
+    id: usize,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+/// Bounding volume hierarchy over the objects of a scene or the triangles of a mesh, split
+/// with the surface area heuristic
+#[derive(Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive ids in leaf order
+    primitives: Vec<usize>,
+    /// Primitives without a bounding box, like planes, which every ray is tested against
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    /// Build over the primitives `0..bounds.len()`, `None` marks an unbounded primitive
+    pub fn new(bounds: impl IntoIterator<Item = Option<Aabb>>) -> Self {
+        let mut items = Vec::new();
+        let mut unbounded = Vec::new();
+
+        for (id, bounds) in bounds.into_iter().enumerate() {
+            match bounds {
+                Some(bounds) => {
+                    let bounds = bounds.padded();
+                    items.push(Item {
+                        id,
+                        bounds,
+                        centroid: bounds.centroid(),
+                    });
+                }
+                None => unbounded.push(id),
+            }
+        }
+
+        let mut nodes = Vec::new();
+
+        if !items.is_empty() {
+            nodes.push(Node {
+                bounds: Aabb::empty(),
+                start: 0,
+                count: 0,
+            });
+            build(&mut nodes, 0, &mut items, 0, 0);
+        }
+
+        Self {
+            nodes,
+            primitives: items.iter().map(|item| item.id).collect(),
+            unbounded,
+        }
+    }
+
+    /// Closest hit between `t_min` and `t_max`, `hit(id, t_max)` intersects a single primitive.
+    /// Gives exactly the same hit as testing every primitive in order, on equal distances the
+    /// lowest id wins.
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: impl FnMut(usize, f32) -> Option<HitRecord>,
+    ) -> Option<HitRecord> {
+        let mut closest = Closest::new(t_max, hit);
+
+        for &id in &self.unbounded {
+            closest.test(id);
+        }
+
+        if self.nodes.is_empty() {
+            return closest.hit_record;
+        }
+
+        let inverse_direction = Vec3::one() / ray.direction;
+        let mut stack = Stack::new();
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+
+            if node
+                .bounds
+                .hit(ray, &inverse_direction, t_min, closest.t_max)
+                .is_none()
+            {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &id in &self.primitives[node.start..node.start + node.count] {
+                    closest.test(id);
+                }
+                continue;
+            }
+
+            let first = &self.nodes[node.start].bounds;
+            let second = &self.nodes[node.start + 1].bounds;
+            let t_first = first.hit(ray, &inverse_direction, t_min, closest.t_max);
+            let t_second = second.hit(ray, &inverse_direction, t_min, closest.t_max);
+
+            // Push the farther child first so the nearer one is visited first
+            match (t_first, t_second) {
+                (Some(a), Some(b)) if a <= b => {
+                    stack.push(node.start + 1);
+                    stack.push(node.start);
+                }
+                (Some(_), Some(_)) => {
+                    stack.push(node.start);
+                    stack.push(node.start + 1);
+                }
+                (Some(_), None) => stack.push(node.start),
+                (None, Some(_)) => stack.push(node.start + 1),
+                (None, None) => {}
+            }
+        }
+
+        closest.hit_record
+    }
+}
+
+/// Nodes left to visit, without allocating. Every level of the tree leaves at most one
+/// sibling behind and the last pushes both children, so `MAX_DEPTH + 1` entries are enough.
+struct Stack {
+    nodes: [usize; MAX_DEPTH + 1],
+    len: usize,
+}
+
+impl Stack {
+    /// Starts with the root
+    fn new() -> Self {
+        Self {
+            nodes: [0; MAX_DEPTH + 1],
+            len: 1,
+        }
+    }
+
+    fn push(&mut self, node: usize) {
+        self.nodes[self.len] = node;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.nodes[self.len])
+    }
+}
+
+/// Closest hit so far while walking the tree
+struct Closest<F> {
+    hit: F,
+    hit_record: Option<HitRecord>,
+    id: usize,
+    t_max: f32,
+}
+
+impl<F: FnMut(usize, f32) -> Option<HitRecord>> Closest<F> {
+    fn new(t_max: f32, hit: F) -> Self {
+        Self {
+            hit,
+            hit_record: None,
+            id: usize::MAX,
+            t_max,
+        }
+    }
+
+    fn test(&mut self, id: usize) {
+        // A lower id also wins when the distance is equal, like it would in a linear search
+        let t_max = if self.hit_record.is_some() && id < self.id {
+            self.t_max.next_up()
+        } else {
+            self.t_max
+        };
+
+        if let Some(hit_record) = (self.hit)(id, t_max) {
+            self.t_max = hit_record.t;
+            self.id = id;
+            self.hit_record = Some(hit_record);
+        }
+    }
+}
+
+fn build(nodes: &mut Vec<Node>, index: usize, items: &mut [Item], start: usize, depth: usize) {
+    let bounds = items
+        .iter()
+        .fold(Aabb::empty(), |aabb, item| aabb.union(&item.bounds));
+
+    let split = sah_split(items, &bounds)
+        .filter(|&(_, _, cost)| cost < items.len() as f32 && depth < MAX_DEPTH);
+
+    let Some((axis, split, _)) = split else {
+        nodes[index] = Node {
+            bounds,
+            start,
+            count: items.len(),
+        };
+        return;
+    };
+
+    let centroids = centroid_bounds(items);
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bin(&centroids, axis, items[i].centroid) < split {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    let first = nodes.len();
+    nodes.extend([nodes[index]; 2]);
+    nodes[index] = Node {
+        bounds,
+        start: first,
+        count: 0,
+    };
+
+    let (left, right) = items.split_at_mut(mid);
+    build(nodes, first, left, start, depth + 1);
+    build(nodes, first + 1, right, start + mid, depth + 1);
+}
+
+fn centroid_bounds(items: &[Item]) -> Aabb {
+    Aabb::from_points(items.iter().map(|item| item.centroid))
+}
+
+fn bin(centroids: &Aabb, axis: usize, centroid: Vec3) -> usize {
+    let extent = centroids.max[axis] - centroids.min[axis];
+    let bin = ((centroid[axis] - centroids.min[axis]) / extent * SAH_BINS as f32) as usize;
+    bin.min(SAH_BINS - 1)
+}
+
+/// Cheapest binned split as (axis, first bin of the right side, cost), in units of object
+/// intersections. `None` if the centroids can't be separated.
+fn sah_split(items: &[Item], bounds: &Aabb) -> Option<(usize, usize, f32)> {
+    if items.len() < 2 {
+        return None;
+    }
+
+    let centroids = centroid_bounds(items);
+    let parent_area = bounds.surface_area();
+    let mut best: Option<(usize, usize, f32)> = None;
+
+    for axis in 0..3 {
+        if centroids.max[axis] - centroids.min[axis] <= 0.0 {
+            continue;
+        }
+
+        let mut counts = [0; SAH_BINS];
+        let mut bin_bounds = [Aabb::empty(); SAH_BINS];
+
+        for item in items {
+            let bin = bin(&centroids, axis, item.centroid);
+            counts[bin] += 1;
+            bin_bounds[bin] = bin_bounds[bin].union(&item.bounds);
+        }
+
+        for split in 1..SAH_BINS {
+            let (left_count, left_bounds) = (0..split).fold((0, Aabb::empty()), |(n, b), i| {
+                (n + counts[i], b.union(&bin_bounds[i]))
+            });
+            let (right_count, right_bounds) = (split..SAH_BINS)
+                .fold((0, Aabb::empty()), |(n, b), i| {
+                    (n + counts[i], b.union(&bin_bounds[i]))
+                });
+
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + (left_bounds.surface_area() * left_count as f32
+                    + right_bounds.surface_area() * right_count as f32)
+                    / parent_area;
+
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, split, cost));
+            }
+        }
+    }
+
+    best
+}
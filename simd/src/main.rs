@@ -1,53 +1,211 @@
 #![feature(portable_simd)]
 #![feature(repr_simd)]
+use clap::{Args, Parser, Subcommand};
 use microbench::{self, Options};
-use std::{fs, path::Path};
+use std::path::PathBuf;
+use std::process;
 
 use crate::math::*;
 use crate::ray::*;
 // use crate::primitives::*;
 use crate::material::*;
+use crate::mesh::*;
 use crate::object::*;
+use crate::output::{Format, Overwrite};
 
+mod bvh;
 mod math;
 mod ray;
 // mod primitives;
 mod material;
 mod mesh;
 mod object;
+mod output;
+mod scene;
 // mod lights;
 
-const WIDTH: u32 = 128;
-const HEIGHT: u32 = WIDTH;
-const VIEWPORT_DISTANCE: f32 = 1.0;
+#[derive(Parser)]
+#[command(about = "SIMD path tracer")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-const SKY_COLOR: Vec3 = /* Vec3 { x: 0.0, y: 0.0, z: 0.0 }; */
-    Vec3 {
-        x: 0.5,
-        y: 0.7,
-        z: 1.0,
-    };
+#[derive(Subcommand)]
+enum Command {
+    /// Render the scene to an image
+    Render {
+        #[command(flatten)]
+        settings: Settings,
+
+        /// Where to write the image, missing directories are created
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Image format, picked from the output extension if not given
+        #[arg(short, long, value_enum)]
+        format: Option<Format>,
+
+        /// What to do if the output file already exists
+        #[arg(long, value_enum, default_value_t)]
+        overwrite: Overwrite,
+    },
+    /// Benchmark rendering the scene
+    Bench {
+        #[command(flatten)]
+        settings: Settings,
+    },
+    /// Benchmark rays against a triangle mesh with and without its BVH
+    BenchMesh {
+        /// Approximate number of triangles in the test mesh
+        #[arg(short, long, default_value_t = 100_000, value_parser = clap::value_parser!(u32).range(2..))]
+        triangles: u32,
+    },
+    /// Print the render settings without rendering
+    Info {
+        #[command(flatten)]
+        settings: Settings,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Settings {
+    /// Scene description file in the cpu renderer's format, uses the built-in scene if not
+    /// given. Only its spheres, planes, meshes, point lights and diffuse and light materials
+    /// are read.
+    scene: Option<String>,
+
+    /// Image size as WIDTHxHEIGHT
+    #[arg(short, long, default_value = "128x128", value_parser = parse_resolution)]
+    resolution: (u32, u32),
+
+    /// Samples per pixel
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: u32,
+
+    /// Maximum number of bounces per path
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    depth: u32,
+
+    /// Distance from the camera to the viewport
+    #[arg(long, default_value_t = 1.0, value_parser = parse_positive)]
+    viewport_distance: f32,
+
+    /// Camera position as X,Y,Z
+    #[arg(long, default_value = "0,0,0", value_parser = parse_vec3)]
+    camera: Vec3,
+
+    /// Color of rays that leave the scene as R,G,B
+    #[arg(long, default_value = "0.5,0.7,1.0", value_parser = parse_vec3)]
+    sky_color: Vec3,
+}
 
-const CAMERA_POSITION: Vec3 = Vec3 {
-    x: 0.0,
-    y: 0.0,
-    z: 0.0,
-};
+impl Settings {
+    fn width(&self) -> u32 {
+        self.resolution.0
+    }
 
-const MAX_DEPTH: u32 = 10;
-const NUM_SAMPLES: u32 = 10;
+    fn height(&self) -> u32 {
+        self.resolution.1
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got `{}`", s))?;
+    let width = width
+        .parse::<u32>()
+        .map_err(|e| format!("invalid width `{}`: {}", width, e))?;
+    let height = height
+        .parse::<u32>()
+        .map_err(|e| format!("invalid height `{}`: {}", height, e))?;
+
+    if width == 0 || height == 0 {
+        return Err("width and height must be at least 1".to_string());
+    }
+
+    Ok((width, height))
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f32>().map_err(|e| format!("invalid number `{}`: {}", v, e)))
+        .collect::<Result<Vec<f32>, String>>()?;
+
+    match values.as_slice() {
+        [x, y, z] => Ok(Vec3::from(*x, *y, *z)),
+        _ => Err(format!("expected three comma separated numbers, got `{}`", s)),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        Ok(value) => Err(format!("must be a positive number, got {}", value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
 fn main() {
-    let mut current = 0;
-    let mut current_path = format!("cpu/images/image{}.ppm", current);
-    while Path::new(&current_path).exists() {
-        current += 1;
-        current_path = format!("cpu/images/image{}.ppm", current);
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Render {
+            settings,
+            output,
+            format,
+            overwrite,
+        } => {
+            let objects = load_objects(&settings);
+
+            let format = output::check(&output, format, overwrite).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            });
+
+            let image = cpu_compute(&objects, &settings);
+
+            match format
+                .encode(settings.width(), settings.height(), &image)
+                .and_then(|data| output::write(&output, overwrite, &data))
+            {
+                Ok(path) => println!("image path: {}", path.display()),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
+            }
+        }
+        Command::Bench { settings } => {
+            let objects = load_objects(&settings);
+            let options = Options::default();
+            microbench::bench(&options, "simd_compute", || {
+                cpu_compute(&objects, &settings)
+            });
+        }
+        Command::BenchMesh { triangles } => bench_mesh(triangles),
+        Command::Info { settings } => {
+            let objects = load_objects(&settings);
+            println!("objects: {}", objects.len());
+            println!("{:#?}", settings);
+        }
     }
-    println!("image path: {}", current_path);
+}
 
-    let mut image = format!("P3\n{} {}\n255\n", WIDTH, HEIGHT);
+fn load_objects(settings: &Settings) -> Vec<Object> {
+    let Some(path) = &settings.scene else {
+        return default_objects();
+    };
 
+    scene::load(path).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    })
+}
+
+fn default_objects() -> Vec<Object> {
     let mut objects = Vec::new();
     objects.push(Object::sphere(
         Vec3::from(0.0, 0.0, 4.0),
@@ -76,54 +234,88 @@ fn main() {
         objects.len(),
     ));
 
+    objects
+}
+
+/// Compare casting random rays at a UV sphere mesh with its BVH and by testing every triangle
+fn bench_mesh(triangles: u32) {
+    let segments = ((triangles as f32 / 2.0).sqrt().ceil() as usize).max(2);
+    let mesh = Mesh::uv_sphere(Vec3::zero(), 1.0, segments, segments);
+    println!("mesh triangles: {}", mesh.triangles.len());
+
+    let rays = (0..1000)
+        .map(|_| {
+            let origin = Vec3::random_unit_vector() * 3.0;
+            let target = Vec3::random_unit_vector() * rand::random::<f32>() * 1.2;
+            Ray {
+                origin,
+                direction: (target - origin).normalized(),
+            }
+        })
+        .collect::<Vec<Ray>>();
+
     let options = Options::default();
-    microbench::bench(&options, "simd_compute", || {
-        cpu_compute(&objects, &mut image)
+    microbench::bench(&options, "mesh_bvh", || {
+        rays.iter()
+            .filter(|ray| mesh.hit(ray, 0.01, f32::MAX, 0).is_some())
+            .count()
+    });
+    microbench::bench(&options, "mesh_linear", || {
+        rays.iter()
+            .filter(|ray| mesh.hit_linear(ray, 0.01, f32::MAX, 0).is_some())
+            .count()
     });
 
-    println!("image path: {}", &current_path);
-    fs::write(&current_path, image).unwrap();
+    let bvh_hits = rays
+        .iter()
+        .map(|ray| mesh.hit(ray, 0.01, f32::MAX, 0).map(|hit| hit.t))
+        .collect::<Vec<Option<f32>>>();
+    let linear_hits = rays
+        .iter()
+        .map(|ray| mesh.hit_linear(ray, 0.01, f32::MAX, 0).map(|hit| hit.t))
+        .collect::<Vec<Option<f32>>>();
+
+    if bvh_hits != linear_hits {
+        eprintln!("bvh and linear hits differ");
+        process::exit(1);
+    }
 }
 
-fn cpu_compute(objects: &Vec<Object>, image: &mut String) {
+/// Renders the image as rows of `settings.width()` pixels, starting from the top row with +y up
+/// the way PNG and PPM store them
+fn cpu_compute(objects: &Vec<Object>, settings: &Settings) -> Vec<Vec3> {
     let mut data = Vec::new();
 
-    for x in 0..WIDTH {
-        for y in 0..HEIGHT {
-            let viewport_x = (x as f32 - WIDTH as f32 / 2.0) / WIDTH as f32;
-            let viewport_y = (y as f32 - HEIGHT as f32 / 2.0) / HEIGHT as f32;
+    let width = settings.width() as f32;
+    let height = settings.height() as f32;
+
+    for y in (0..settings.height()).rev() {
+        for x in 0..settings.width() {
+            let viewport_x = (x as f32 - width / 2.0) / width;
+            let viewport_y = (y as f32 - height / 2.0) / height;
 
-            let average_color = (0..NUM_SAMPLES).fold(Vec3::zero(), |acc, _| {
+            let average_color = (0..settings.samples).fold(Vec3::zero(), |acc, _| {
                 let ray = Ray {
-                    origin: CAMERA_POSITION,
-                    direction: Vec3 {
-                        x: viewport_x - (0.5 + rand::random::<f32>()) / WIDTH as f32,
-                        y: viewport_y - (0.5 + rand::random::<f32>()) / HEIGHT as f32,
-                        z: VIEWPORT_DISTANCE,
-                    }
+                    origin: settings.camera,
+                    direction: Vec3::from(
+                        viewport_x - (0.5 + rand::random::<f32>()) / width,
+                        viewport_y - (0.5 + rand::random::<f32>()) / height,
+                        settings.viewport_distance,
+                    )
                     .normalized(),
                 };
-                acc + ray_caste(&ray, &objects, MAX_DEPTH)
-            }) / NUM_SAMPLES as f32;
+                acc + ray_caste(&ray, &objects, settings.sky_color, settings.depth)
+            }) / settings.samples as f32;
 
             data.push(average_color);
             //println!("pixel: {} / {}", x * WIDTH + y, WIDTH * HEIGHT);
         }
     }
 
-    *image += data
-        .iter()
-        .map(|x| Color::from(*x).to_string())
-        .collect::<Vec<String>>()
-        .chunks(WIDTH as usize)
-        .map(|x| x.join(" "))
-        .collect::<Vec<String>>()
-        .as_slice()
-        .join("\n")
-        .as_str();
+    data
 }
 
-fn ray_caste(ray: &Ray, objects: &Vec<Object>, depth: u32) -> Vec3 {
+fn ray_caste(ray: &Ray, objects: &Vec<Object>, sky_color: Vec3, depth: u32) -> Vec3 {
     if depth <= 0 {
         return Vec3::zero();
     }
@@ -144,14 +336,14 @@ fn ray_caste(ray: &Ray, objects: &Vec<Object>, depth: u32) -> Vec3 {
             .scatter(ray, &hit_record);
 
         if let Some(scattered) = scattered {
-            return attenuation * ray_caste(&scattered, objects, depth - 1);
+            return attenuation * ray_caste(&scattered, objects, sky_color, depth - 1);
         } else {
             return attenuation;
         }
     }
 
     if depth == 0 {
-        return SKY_COLOR;
+        return sky_color;
     }
 
     Vec3::zero() //SKY_COLOR
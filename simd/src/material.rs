@@ -24,9 +24,9 @@ impl Color {
 impl From<Vec3> for Color {
     fn from(vec: Vec3) -> Self {
         Self {
-            r: (vec.x * 255.0) as u8,
-            g: (vec.y * 255.0) as u8,
-            b: (vec.z * 255.0) as u8,
+            r: (vec.x() * 255.0) as u8,
+            g: (vec.y() * 255.0) as u8,
+            b: (vec.z() * 255.0) as u8,
         }
     }
 }
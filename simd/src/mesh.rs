@@ -1,3 +1,4 @@
+use crate::bvh::*;
 use crate::object::*;
 //use crate::primitives::*;
 use crate::math::*;
@@ -83,24 +84,99 @@ impl MeshTrait for Plane {
 pub struct Mesh {
     pub triangles: Vec<Triangle>,
     pub normals: Vec<Vec3>,
+    bvh: Bvh,
 }
 
-impl MeshTrait for Mesh {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
+impl Mesh {
+    /// Create a mesh and build the BVH over its triangles
+    pub fn new(triangles: Vec<Triangle>, normals: Vec<Vec3>) -> Self {
+        let bvh = Bvh::new(triangles.iter().map(|triangle| Some(triangle.bounding_box())));
+
+        Self {
+            triangles,
+            normals,
+            bvh,
+        }
+    }
+
+    /// Sphere made of `rings` bands of `segments` quads, split into triangles
+    pub fn uv_sphere(center: Vec3, radius: f32, rings: usize, segments: usize) -> Self {
+        let point = |ring: usize, segment: usize| {
+            let theta = ring as f32 / rings as f32 * std::f32::consts::PI;
+            let phi = segment as f32 / segments as f32 * std::f32::consts::TAU;
+            center
+                + Vec3::from(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ) * radius
+        };
+
+        let mut triangles = Vec::new();
+
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = point(ring, segment);
+                let b = point(ring + 1, segment);
+                let c = point(ring, segment + 1);
+                let d = point(ring + 1, segment + 1);
+                triangles.push(Triangle::new(a, b, c));
+                triangles.push(Triangle::new(c, b, d));
+            }
+        }
+
+        Self::new(triangles, Vec::new())
+    }
+
+    /// Test every triangle in order without the BVH, used to benchmark it
+    pub fn hit_linear(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        object_id: usize,
+    ) -> Option<HitRecord> {
         let mut closest_hit: Option<HitRecord> = None;
 
-        for triangle in &self.triangles {
-            if let Some(hit) = triangle.hit(ray, t_min, t_max, object_id) {
-                if let Some(closest) = closest_hit {
-                    if hit.t < closest.t {
-                        closest_hit = Some(hit);
-                    }
-                } else {
-                    closest_hit = Some(hit);
-                }
+        for triangle in 0..self.triangles.len() {
+            let t_max = closest_hit.as_ref().map_or(t_max, |closest| closest.t);
+
+            if let Some(hit) = self.hit_triangle(triangle, ray, t_min, t_max, object_id) {
+                closest_hit = Some(hit);
             }
         }
 
         closest_hit
     }
+
+    fn hit_triangle(
+        &self,
+        triangle: usize,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        object_id: usize,
+    ) -> Option<HitRecord> {
+        let triangle = &self.triangles[triangle];
+        let t = triangle.intersect(ray)?;
+
+        if t < t_max && t > t_min {
+            return Some(HitRecord {
+                point: ray.at(t),
+                normal: triangle.normal(),
+                t,
+                object_id,
+            });
+        }
+
+        None
+    }
+}
+
+impl MeshTrait for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, object_id: usize) -> Option<HitRecord> {
+        self.bvh.hit(ray, t_min, t_max, |triangle, t_max| {
+            self.hit_triangle(triangle, ray, t_min, t_max, object_id)
+        })
+    }
 }
@@ -0,0 +1,202 @@
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::material::Color;
+use crate::math::Vec3;
+
+/// What to do when the output file already exists
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Overwrite {
+    /// Fail without touching the existing file
+    #[default]
+    Never,
+    /// Replace the existing file
+    Always,
+    /// Write to the first free name, image.ppm, image1.ppm, image2.ppm, ...
+    Increment,
+}
+
+/// Image file format, picked from the file extension unless given explicitly
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// 8-bit RGB PNG
+    Png,
+    /// Binary PPM (P6)
+    Ppm,
+    /// ASCII PPM (P3)
+    PpmAscii,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "ppm" => Some(Self::Ppm),
+            _ => None,
+        }
+    }
+
+    /// Encode an image stored as rows of `width` pixels
+    pub fn encode(self, width: u32, height: u32, image: &[Vec3]) -> Result<Vec<u8>, OutputError> {
+        match self {
+            Self::Png => png(width, height, image),
+            Self::Ppm => Ok(ppm_binary(width, height, image)),
+            Self::PpmAscii => Ok(ppm(width, height, image)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    Exists(PathBuf),
+    UnknownFormat(PathBuf),
+    Png(png::EncodingError),
+    Io { path: PathBuf, error: io::Error },
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exists(path) => write!(
+                f,
+                "{} already exists, use --overwrite always or --overwrite increment",
+                path.display()
+            ),
+            Self::UnknownFormat(path) => write!(
+                f,
+                "can't tell the image format of {}, use a .png or .ppm extension or --format",
+                path.display()
+            ),
+            Self::Png(error) => write!(f, "png encoding failed: {}", error),
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+/// Fail early, before rendering, if the image could not be written with this policy.
+/// Returns the format to write, `format` or the one matching the extension of `path`.
+pub fn check(
+    path: &Path,
+    format: Option<Format>,
+    overwrite: Overwrite,
+) -> Result<Format, OutputError> {
+    let format = format
+        .or_else(|| Format::from_path(path))
+        .ok_or_else(|| OutputError::UnknownFormat(path.to_path_buf()))?;
+
+    if overwrite == Overwrite::Never && path.exists() {
+        return Err(OutputError::Exists(path.to_path_buf()));
+    }
+
+    Ok(format)
+}
+
+/// Write `data` to `path`, creating missing directories. Returns the path that was written,
+/// which differs from `path` with `Overwrite::Increment`.
+pub fn write(path: &Path, overwrite: Overwrite, data: &[u8]) -> Result<PathBuf, OutputError> {
+    let io_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |error| OutputError::Io { path, error }
+    };
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+
+    let path = match overwrite {
+        Overwrite::Increment => free_path(path),
+        _ => path.to_path_buf(),
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(overwrite != Overwrite::Always)
+        .open(&path)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::AlreadyExists => OutputError::Exists(path.clone()),
+            _ => OutputError::Io {
+                path: path.clone(),
+                error,
+            },
+        })?;
+
+    file.write_all(data).map_err(io_error(&path))?;
+
+    Ok(path)
+}
+
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| e.to_string_lossy());
+    let mut current = 0;
+    let mut current_path = path.to_path_buf();
+
+    while current_path.exists() {
+        current += 1;
+        current_path = path.with_file_name(match &extension {
+            Some(extension) => format!("{}{}.{}", stem, current, extension),
+            None => format!("{}{}", stem, current),
+        });
+    }
+
+    current_path
+}
+
+fn rgb8(image: &[Vec3]) -> Vec<u8> {
+    image
+        .iter()
+        .flat_map(|x| {
+            let color = Color::from(*x);
+            [color.r, color.g, color.b]
+        })
+        .collect()
+}
+
+fn ppm(width: u32, height: u32, image: &[Vec3]) -> Vec<u8> {
+    let mut ppm = format!("P3\n{} {}\n255\n", width, height);
+
+    ppm += image
+        .iter()
+        .map(|x| Color::from(*x).to_string())
+        .collect::<Vec<String>>()
+        .chunks(width as usize)
+        .map(|x| x.join(" "))
+        .collect::<Vec<String>>()
+        .as_slice()
+        .join("\n")
+        .as_str();
+
+    ppm.into_bytes()
+}
+
+fn ppm_binary(width: u32, height: u32, image: &[Vec3]) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    ppm.extend(rgb8(image));
+    ppm
+}
+
+fn png(width: u32, height: u32, image: &[Vec3]) -> Result<Vec<u8>, OutputError> {
+    let mut data = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut data, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(OutputError::Png)?;
+    writer
+        .write_image_data(&rgb8(image))
+        .map_err(OutputError::Png)?;
+    writer.finish().map_err(OutputError::Png)?;
+
+    Ok(data)
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::{fs, io};
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::material::*;
+use crate::math::*;
+use crate::mesh::*;
+use crate::object::*;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl SceneError {
+    fn parse(path: &str, source: &str, offset: usize, message: impl Into<String>) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+        Self::Parse {
+            path: path.to_string(),
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {}", path, error),
+            Self::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// The part of the cpu renderer's scene format this renderer can draw
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    /// Read by the cpu renderer, here the camera, sky and settings come from the command line
+    #[serde(default, rename = "camera")]
+    _camera: Option<IgnoredAny>,
+    #[serde(default, rename = "sky")]
+    _sky: Option<IgnoredAny>,
+    #[serde(default, rename = "sky_color")]
+    _sky_color: Option<IgnoredAny>,
+    #[serde(default, rename = "render")]
+    _render: Option<IgnoredAny>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    spheres: Vec<SphereDescription>,
+    #[serde(default)]
+    planes: Vec<PlaneDescription>,
+    #[serde(default)]
+    meshes: Vec<MeshDescription>,
+    #[serde(default)]
+    point_lights: Vec<PointLightDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MaterialDescription {
+    Diffuse { color: Vec3 },
+    Light { color: Vec3 },
+    /// Any other type the cpu renderer knows, rejected when an object uses it
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDescription {
+    center: Vec3,
+    radius: Spanned<f32>,
+    material: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDescription {
+    point: Vec3,
+    normal: Spanned<Vec3>,
+    material: Spanned<String>,
+}
+
+/// Triangles in world space, this renderer has no object transforms
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDescription {
+    vertices: Vec<Vec3>,
+    indices: Spanned<Vec<usize>>,
+    material: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightDescription {
+    position: Vec3,
+    radius: Spanned<f32>,
+    color: Vec3,
+    /// Deprecated and ignored, like in the cpu renderer
+    #[serde(default)]
+    range: Option<f32>,
+}
+
+/// Read and build the objects of the scene at `path`
+pub fn load(path: &str) -> Result<Vec<Object>, SceneError> {
+    let source = fs::read_to_string(path).map_err(|error| SceneError::Io {
+        path: path.to_string(),
+        error,
+    })?;
+
+    parse(&source, path)
+}
+
+/// Build the objects from TOML source, `path` is only used in error messages
+pub fn parse(source: &str, path: &str) -> Result<Vec<Object>, SceneError> {
+    let file: SceneFile = toml::from_str(source).map_err(|error| {
+        let offset = error.span().map_or(0, |span| span.start);
+        SceneError::parse(path, source, offset, error.message())
+    })?;
+
+    let material = |name: &Spanned<String>| {
+        let error = |message: String| SceneError::parse(path, source, name.span().start, message);
+
+        match file.materials.get(name.get_ref()) {
+            Some(MaterialDescription::Diffuse { color }) => Ok(Diffuse::boxed(*color)),
+            Some(MaterialDescription::Light { color }) => {
+                Ok(Box::new(PointLightMaterial { color: *color }) as Box<dyn Material>)
+            }
+            Some(MaterialDescription::Unsupported) => Err(error(format!(
+                "material `{}` isn't diffuse or light, the only types the simd renderer has",
+                name.get_ref()
+            ))),
+            None => Err(error(format!("unknown material `{}`", name.get_ref()))),
+        }
+    };
+
+    let radius = |radius: &Spanned<f32>| match *radius.get_ref() {
+        value if value > 0.0 => Ok(value),
+        _ => Err(SceneError::parse(
+            path,
+            source,
+            radius.span().start,
+            "radius must be positive",
+        )),
+    };
+
+    let mut objects = Vec::new();
+
+    for sphere in &file.spheres {
+        objects.push(Object::sphere(
+            sphere.center,
+            radius(&sphere.radius)?,
+            material(&sphere.material)?,
+            objects.len(),
+        ));
+    }
+
+    for plane in &file.planes {
+        if plane.normal.get_ref().length() == 0.0 {
+            return Err(SceneError::parse(
+                path,
+                source,
+                plane.normal.span().start,
+                "plane normal can't be zero",
+            ));
+        }
+
+        objects.push(Object::plane(
+            plane.point,
+            plane.normal.get_ref().normalized(),
+            material(&plane.material)?,
+            objects.len(),
+        ));
+    }
+
+    for mesh in &file.meshes {
+        let indices = mesh.indices.get_ref();
+
+        if indices.len() % 3 != 0 {
+            return Err(SceneError::parse(
+                path,
+                source,
+                mesh.indices.span().start,
+                "mesh indices must come in groups of three",
+            ));
+        }
+
+        if let Some(index) = indices.iter().find(|&&i| i >= mesh.vertices.len()) {
+            return Err(SceneError::parse(
+                path,
+                source,
+                mesh.indices.span().start,
+                format!(
+                    "mesh index {} is out of range for {} vertices",
+                    index,
+                    mesh.vertices.len()
+                ),
+            ));
+        }
+
+        let triangles = indices
+            .chunks(3)
+            .map(|i| Triangle::new(mesh.vertices[i[0]], mesh.vertices[i[1]], mesh.vertices[i[2]]))
+            .collect();
+
+        objects.push(Object::from_mesh(
+            Vec3::zero(),
+            Mesh::new(triangles, Vec::new()),
+            material(&mesh.material)?,
+            objects.len(),
+        ));
+    }
+
+    for light in &file.point_lights {
+        objects.push(Object::point_light(
+            light.position,
+            radius(&light.radius)?,
+            light.range.unwrap_or(0.0),
+            light.color,
+            objects.len(),
+        ));
+    }
+
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_position(source: &str) -> (usize, usize, String) {
+        match parse(source, "test.toml") {
+            Err(SceneError::Parse {
+                line,
+                column,
+                message,
+                ..
+            }) => (line, column, message),
+            Err(error) => panic!("wrong error: {}", error),
+            Ok(_) => panic!("parsed"),
+        }
+    }
+
+    #[test]
+    fn reads_the_cpu_default_scene() {
+        let objects = parse(
+            include_str!("../../cpu/scenes/default.toml"),
+            "default.toml",
+        )
+        .unwrap();
+
+        assert_eq!(objects.len(), 4);
+        assert!(objects.iter().enumerate().all(|(i, object)| object.id == i));
+    }
+
+    #[test]
+    fn builds_meshes_from_indices() {
+        let source = r#"
+            [materials.white]
+            type = "diffuse"
+            color = [1.0, 1.0, 1.0]
+
+            [[meshes]]
+            vertices = [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0]]
+            indices = [0, 1, 2, 2, 1, 3]
+            material = "white"
+        "#;
+        let objects = parse(source, "test.toml").unwrap();
+        let ray = crate::ray::Ray {
+            origin: Vec3::from(0.75, 0.75, 0.0),
+            direction: Vec3::from(0.0, 0.0, 1.0),
+        };
+
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].hit(&ray, 0.0, f32::MAX).unwrap().t, 1.0);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let source = "[materials.glass]\ntype = \"dielectric\"\nrefractive_index = 1.5\n\n\
+                      [[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\nmaterial = \"glass\"\n";
+        let (line, column, message) = error_position(source);
+        assert_eq!((line, column), (8, 12));
+        assert!(message.contains("isn't diffuse or light"), "{}", message);
+
+        let (line, _, message) = error_position("[[quads]]\ncorner = [0.0, 0.0, 0.0]\n");
+        assert_eq!(line, 1);
+        assert!(message.contains("unknown field `quads`"), "{}", message);
+
+        let source =
+            "[[point_lights]]\nposition = [0.0, 0.0, 0.0]\nradius = 0.0\ncolor = [1.0, 1.0, 1.0]\n";
+        assert_eq!(
+            error_position(source),
+            (3, 10, "radius must be positive".to_string())
+        );
+    }
+}